@@ -0,0 +1,65 @@
+//! The Game Boy cartridge header, which the boot ROM checks before starting the game.
+
+use std::ops::Range;
+
+/// Where the header lives in the ROM.
+pub const HEADER: Range<u16> = 0x0100..0x0150;
+
+/// The longest title that fits before the CGB flag.
+pub const MAX_TITLE_LENGTH: usize = 15;
+
+/// The logo the boot ROM compares against, a cartridge without it doesn't boot.
+const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+const TITLE: usize = 0x0134;
+const DESTINATION: usize = 0x014A;
+const ROM_SIZE: usize = 0x0148;
+const HEADER_CHECKSUM: usize = 0x014D;
+const GLOBAL_CHECKSUM: Range<usize> = 0x014E..0x0150;
+
+/// The header with an entry point jumping to `entry`. The ROM size and checksums depend on the whole ROM,
+/// so they're filled in by `finish`.
+pub fn header(title: &str, entry: u16) -> [u8; 0x50] {
+    let mut header = [0; 0x50];
+    let offset = usize::from(HEADER.start);
+
+    // `nop` followed by `jp entry`
+    let [entry_low, entry_high] = entry.to_le_bytes();
+    header[..4].copy_from_slice(&[0x00, 0xC3, entry_low, entry_high]);
+    header[4..52].copy_from_slice(&NINTENDO_LOGO);
+
+    let title_start = TITLE - offset;
+    header[title_start..title_start + title.len()].copy_from_slice(title.as_bytes());
+
+    // Outside of Japan, the cartridge type and RAM size are left at 0 for a plain ROM.
+    header[DESTINATION - offset] = 0x01;
+
+    header
+}
+
+/// Pads the ROM to a size the header can describe, and fills in the ROM size and both checksums.
+pub fn finish(binary: &mut Vec<u8>) {
+    let size = binary.len().max(0x8000).next_power_of_two();
+    binary.resize(size, 0);
+
+    binary[ROM_SIZE] = (size / 0x8000).trailing_zeros() as u8;
+
+    binary[HEADER_CHECKSUM] = binary[TITLE..HEADER_CHECKSUM]
+        .iter()
+        .fold(0u8, |checksum, byte| {
+            checksum.wrapping_sub(*byte).wrapping_sub(1)
+        });
+
+    let global_checksum = binary
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !GLOBAL_CHECKSUM.contains(i))
+        .fold(0u16, |checksum, (_, byte)| {
+            checksum.wrapping_add(u16::from(*byte))
+        });
+    binary[GLOBAL_CHECKSUM].copy_from_slice(&global_checksum.to_be_bytes());
+}
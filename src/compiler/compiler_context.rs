@@ -1,17 +1,21 @@
 use std::collections::HashMap;
 
+use crate::{CompileError, Span};
+
 pub struct CompilerContext {
     pub address: usize,
     pub binary: Vec<u8>,
 
     pub declarations: HashMap<String, u16>,
+
+    /// Errors that can only be detected once every declaration is known, like out of range jumps.
+    pub errors: Vec<CompileError>,
 }
 
 impl CompilerContext {
     fn reserve_min(&mut self, min_length: usize) {
         if min_length > self.binary.len() {
-            self.binary
-                .extend(std::iter::repeat(0).take(min_length - self.binary.len()));
+            self.binary.resize(min_length, 0);
         }
     }
 
@@ -26,10 +30,14 @@ impl CompilerContext {
         self.address = new_address as usize;
     }
 
-    pub fn set(&mut self, name: &str, value: u16) {
+    /// Declares `name`, reporting an error at `span` if it was already declared.
+    pub fn set(&mut self, name: &str, value: u16, span: &Span) {
         if self.declarations.contains_key(name) {
-            // TODO Compile error?
-            panic!("Already declared");
+            self.errors.push(CompileError {
+                message: format!("'{}' is already declared", name),
+                span: span.clone(),
+            });
+            return;
         }
 
         self.declarations.insert(name.to_owned(), value);
@@ -38,4 +46,17 @@ impl CompilerContext {
     pub fn get(&self, name: &str) -> Option<u16> {
         self.declarations.get(name).copied()
     }
+
+    /// Like `get`, but reports an error at `span` if `name` was never declared.
+    pub fn get_label(&mut self, name: &str, span: &Span) -> Option<u16> {
+        let value = self.get(name);
+        if value.is_none() {
+            self.errors.push(CompileError {
+                message: format!("Unknown label '{}'", name),
+                span: span.clone(),
+            });
+        }
+
+        value
+    }
 }
@@ -1,11 +1,16 @@
 use crate::{
     tokenizer::{Token, TokenType},
-    CompileError,
+    CompileError, Span,
 };
 
 use super::Compiler;
 
 impl<'a> Compiler<'a> {
+    /// The text of `span`, in whichever file it's in.
+    pub fn slice(&self, span: &Span) -> &'a str {
+        span.slice(self.files[span.file].text)
+    }
+
     pub fn next_reset(&mut self) {
         while let Some(next) = self.peek() {
             if next.ty == TokenType::NewLine {
@@ -32,9 +37,9 @@ impl<'a> Compiler<'a> {
         if let Some(Token {
             ty: TokenType::CommentLine,
             ..
-        }) = self.remaining_tokens.get(0)
+        }) = self.remaining_tokens.first()
         {
-            while let Some(next) = self.remaining_tokens.get(0) {
+            while let Some(next) = self.remaining_tokens.first() {
                 if next.ty == TokenType::NewLine {
                     break;
                 }
@@ -47,12 +52,12 @@ impl<'a> Compiler<'a> {
     pub fn peek(&mut self) -> Option<Token> {
         // TODO maybe skip comment line without actually calling .skip()? to keep immutability of this function.
         self.skip_comment_line();
-        self.remaining_tokens.get(0).cloned()
+        self.remaining_tokens.first().cloned()
     }
 
     pub fn next(&mut self) -> Option<Token> {
         self.skip_comment_line();
-        let v = self.remaining_tokens.get(0).cloned();
+        let v = self.remaining_tokens.first().cloned();
         if let Some(v) = &v {
             self.latest_span = v.span.clone();
         }
@@ -60,6 +65,14 @@ impl<'a> Compiler<'a> {
         v
     }
 
+    /// The span of the next token, or the latest one if there are no tokens left.
+    pub fn peek_span(&mut self) -> Span {
+        match self.peek() {
+            Some(token) => token.span,
+            None => self.latest_span.clone(),
+        }
+    }
+
     pub fn skip(&mut self) {
         self.latest_span = self.remaining_tokens[0].span.clone();
         self.remaining_tokens = &self.remaining_tokens[1..];
@@ -67,7 +80,10 @@ impl<'a> Compiler<'a> {
 
     pub fn peek_token_with_type(&mut self, target: TokenType) -> Result<Token, CompileError> {
         let Some(token) = self.peek() else {
-            return Err(CompileError { message: "Expected identifier, found EOF".to_owned(), span: self.latest_span.clone() });
+            return Err(CompileError {
+                message: "Expected identifier, found EOF".to_owned(),
+                span: self.latest_span.clone(),
+            });
         };
 
         self.latest_span = token.span.clone();
@@ -84,7 +100,10 @@ impl<'a> Compiler<'a> {
 
     pub fn read_token_with_type(&mut self, target: TokenType) -> Result<Token, CompileError> {
         let Some(token) = self.next() else {
-            return Err(CompileError { message: "Expected identifier, found EOF".to_owned(), span: self.latest_span.clone() });
+            return Err(CompileError {
+                message: "Expected identifier, found EOF".to_owned(),
+                span: self.latest_span.clone(),
+            });
         };
 
         self.latest_span = token.span.clone();
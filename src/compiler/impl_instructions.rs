@@ -0,0 +1,716 @@
+use crate::{tokenizer::TokenType, CompileError, Span};
+
+use super::{
+    isa::{self, Conditions, Enc, Field, InstructionDef, InstructionSet, Pattern, Target},
+    types::{Condition, DataTarget, Displacement, LongRegister, Register, ShortRegister, Value},
+    Compiler,
+};
+
+/// An operand as written in the source, along with its span for error messages.
+struct Operand {
+    target: DataTarget,
+    span: Span,
+    /// Whether the value is known to be below $100 while parsing, so 6502 zero page forms can be used.
+    zero_page: bool,
+}
+
+impl Operand {
+    fn value(&self) -> Option<&Value> {
+        match &self.target {
+            DataTarget::Immediate(value)
+            | DataTarget::Address(value)
+            | DataTarget::IndexedAddress(value, _)
+            | DataTarget::IndexedIndirect(value)
+            | DataTarget::IndirectIndexed(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// Joins names like `A`, `A or B` and `A, B or C`.
+fn join_or(names: &[String]) -> String {
+    match names {
+        [] => String::new(),
+        [name] => name.clone(),
+        [rest @ .., last] => format!("{} or {}", rest.join(", "), last),
+    }
+}
+
+/// Finds the form of `mnemonic` matching the operands and condition.
+/// Operands are matched one position at a time, so errors can point at the first one that doesn't fit.
+fn find_form(
+    set: InstructionSet,
+    mnemonic: &str,
+    operands: &[Operand],
+    condition: Option<(Condition, Span)>,
+    site_span: &Span,
+) -> Result<&'static InstructionDef, CompileError> {
+    let forms = isa::forms(set, mnemonic).collect::<Vec<_>>();
+    let mut candidates = forms
+        .iter()
+        .copied()
+        .filter(|def| def.operands.len() == operands.len())
+        .collect::<Vec<_>>();
+
+    if candidates.is_empty() {
+        let mut counts = forms
+            .iter()
+            .map(|def| def.operands.len())
+            .collect::<Vec<_>>();
+        counts.sort_unstable();
+        counts.dedup();
+
+        let plural = if counts == [1] { "" } else { "s" };
+        let counts = counts.iter().map(usize::to_string).collect::<Vec<_>>();
+        return Err(CompileError {
+            message: format!(
+                "'{}' expects {} operand{}, found {}",
+                mnemonic,
+                join_or(&counts),
+                plural,
+                operands.len()
+            ),
+            span: site_span.clone(),
+        });
+    }
+
+    for (i, operand) in operands.iter().enumerate() {
+        let index = operands[..i]
+            .iter()
+            .find_map(|operand| operand.target.index_register());
+
+        let matching = candidates
+            .iter()
+            .copied()
+            .filter(|def| {
+                let pattern = def.operands[i];
+                pattern.matches(&operand.target, index)
+                    && (operand.zero_page || !pattern.is_zero_page())
+            })
+            .collect::<Vec<_>>();
+
+        if matching.is_empty() {
+            let mut expected = Vec::new();
+            for def in &candidates {
+                for name in def.operands[i].describe(index) {
+                    if !expected.contains(&name) {
+                        expected.push(name);
+                    }
+                }
+            }
+
+            let role = match i {
+                _ if operands.len() == 1 => "operand",
+                0 => "destination",
+                _ if i == operands.len() - 1 => "source",
+                _ => "operand",
+            };
+
+            let mut context = mnemonic.to_owned();
+            if i > 0 {
+                let previous = operands[..i]
+                    .iter()
+                    .map(|operand| operand.target.to_string())
+                    .collect::<Vec<_>>();
+                context = format!("{} {}", context, previous.join(", "));
+            }
+
+            let one_of = if expected.len() > 2 { "one of " } else { "" };
+            return Err(CompileError {
+                message: format!(
+                    "Invalid {} '{}' for '{}', expected {}{}",
+                    role,
+                    operand.target,
+                    context,
+                    one_of,
+                    join_or(&expected)
+                ),
+                span: operand.span.clone(),
+            });
+        }
+
+        candidates = matching;
+    }
+
+    let Some((condition, condition_span)) = condition else {
+        return Ok(candidates
+            .iter()
+            .find(|def| def.conditions == Conditions::None)
+            .unwrap_or(&candidates[0]));
+    };
+
+    let conditional = candidates
+        .iter()
+        .filter(|def| def.conditions != Conditions::None)
+        .collect::<Vec<_>>();
+
+    if let Some(def) = conditional
+        .iter()
+        .find(|def| def.conditions.allows(condition))
+    {
+        return Ok(def);
+    }
+
+    let message = if conditional
+        .iter()
+        .any(|def| def.conditions == Conditions::ZeroCarry)
+    {
+        format!("'{}' can only be conditional on Zero or Carry", mnemonic)
+    } else {
+        format!("'{}' can't be conditional", mnemonic)
+    };
+
+    Err(CompileError {
+        message,
+        span: condition_span,
+    })
+}
+
+/// Describes where an 8-bit immediate ends up, for range errors.
+fn imm8_destination(operands: &[Operand], index: usize) -> String {
+    if index == 0 {
+        return "an 8-bit operand".to_owned();
+    }
+
+    match &operands[0].target {
+        DataTarget::Register(Register::Short(short_reg)) => format!("the {:?} register", short_reg),
+        DataTarget::RegisterAddress(register) => format!("the memory at {}", register),
+        DataTarget::Indexed(index_reg, _) => format!("the memory at {:?}", index_reg),
+        _ => "an 8-bit operand".to_owned(),
+    }
+}
+
+impl<'a> Compiler<'a> {
+    /// Reads an indexed operand like `(IX + 4)*`, `(IY - Offset)*` or `(IX)*`,
+    /// the stepped `(HL+)*` and `(HL-)*` of the SM83, or the indexed addresses of the 6502.
+    pub fn read_indexed(&mut self) -> Result<DataTarget, CompileError> {
+        self.read_token_with_type(TokenType::OpeningParen)?;
+
+        if self.peek_register().is_err() {
+            return self.read_indexed_address();
+        }
+
+        let register = self.read_register()?;
+        let index_reg = match register {
+            Register::Long(index_reg) if index_reg.index_prefix().is_some() => index_reg,
+            Register::Long(LongRegister::HL) => {
+                let step = self.next();
+                let target = match step.map(|step| step.ty) {
+                    Some(TokenType::Plus) => DataTarget::HLIncrement,
+                    Some(TokenType::Minus) => DataTarget::HLDecrement,
+                    _ => {
+                        return Err(CompileError {
+                            message: "Expected '+' or '-' after HL".to_owned(),
+                            span: self.latest_span.clone(),
+                        })
+                    }
+                };
+
+                self.read_token_with_type(TokenType::ClosingParen)?;
+                self.read_token_with_type(TokenType::Star)?;
+                return Ok(target);
+            }
+            _ => {
+                return Err(CompileError {
+                    message: format!("Expected IX, IY or HL, found {}", register),
+                    span: self.latest_span.clone(),
+                })
+            }
+        };
+
+        let mut displacement = Displacement {
+            value: Value::Literal(0),
+            negative: false,
+            span: self.latest_span.clone(),
+        };
+        if let Some(next) = self.peek() {
+            if next.ty == TokenType::Plus || next.ty == TokenType::Minus {
+                self.skip();
+
+                let value = self.read_value()?;
+                displacement = Displacement {
+                    value,
+                    negative: next.ty == TokenType::Minus,
+                    span: self.latest_span.clone(),
+                };
+            }
+        }
+
+        self.read_token_with_type(TokenType::ClosingParen)?;
+        self.read_token_with_type(TokenType::Star)?;
+
+        Ok(DataTarget::Indexed(index_reg, displacement))
+    }
+
+    /// Reads the rest of a 6502 indexed address after the opening parenthesis,
+    /// `($1000 + X)*`, `($10 + Y)*`, the indexed indirect `($10 + X)**` or the indirect indexed `($10* + Y)*`.
+    fn read_indexed_address(&mut self) -> Result<DataTarget, CompileError> {
+        let start = self.latest_span.clone();
+        let value = self.read_value()?;
+        let pointer = self.skip_star();
+        self.read_token_with_type(TokenType::Plus)?;
+
+        // X and Y are names on targets without them, the operand is still read so it can be reported as a missing register.
+        let named = self
+            .peek()
+            .filter(|token| token.ty == TokenType::Identifier)
+            .and_then(|token| match self.slice(&token.span) {
+                "X" => Some(ShortRegister::X),
+                "Y" => Some(ShortRegister::Y),
+                _ => None,
+            });
+        let index_reg = match named {
+            Some(index_reg) => {
+                self.skip();
+                index_reg
+            }
+            None => match self.read_register()? {
+                Register::Short(index_reg @ (ShortRegister::X | ShortRegister::Y)) => index_reg,
+                register => {
+                    return Err(CompileError {
+                        message: format!("Expected X or Y, found {}", register),
+                        span: self.latest_span.clone(),
+                    })
+                }
+            },
+        };
+
+        self.read_token_with_type(TokenType::ClosingParen)?;
+        self.read_token_with_type(TokenType::Star)?;
+        let indirect = self.skip_star();
+
+        match (pointer, index_reg, indirect) {
+            (false, _, false) => Ok(DataTarget::IndexedAddress(value, index_reg)),
+            (false, ShortRegister::X, true) => Ok(DataTarget::IndexedIndirect(value)),
+            (true, ShortRegister::Y, false) => Ok(DataTarget::IndirectIndexed(value)),
+            _ => Err(CompileError {
+                message: "Pointers can only be indexed like '($10 + X)**' or '($10* + Y)*'"
+                    .to_owned(),
+                span: start.to(&self.latest_span),
+            }),
+        }
+    }
+
+    /// Whether the value of `target` is known to be in the zero page, which only literals
+    /// and declarations before it can be.
+    fn is_zero_page(&self, target: &DataTarget) -> bool {
+        let value = match target {
+            DataTarget::Address(value)
+            | DataTarget::IndexedAddress(value, _)
+            | DataTarget::IndexedIndirect(value)
+            | DataTarget::IndirectIndexed(value) => value,
+            _ => return false,
+        };
+
+        match value {
+            Value::Literal(value) => *value <= 0xFF,
+            Value::Identifier(name) => self
+                .known_values
+                .get(name)
+                .is_some_and(|value| *value <= 0xFF),
+        }
+    }
+
+    pub fn read_data_target(&mut self) -> Result<DataTarget, CompileError> {
+        if let Some(next) = self.peek() {
+            if next.ty == TokenType::OpeningParen {
+                return self.read_indexed();
+            }
+
+            // `&counter` is the address of a declaration, like the name on its own.
+            if next.ty == TokenType::Ampersand {
+                self.skip();
+                let name = self.read_reference()?;
+                return Ok(DataTarget::Immediate(Value::Identifier(name)));
+            }
+        }
+
+        if let Ok(register) = self.peek_register() {
+            assert_eq!(register, self.read_register()?);
+
+            let mut is_address = false;
+            if let Some(next) = self.peek() {
+                if next.ty == TokenType::Star {
+                    self.skip();
+                    is_address = true;
+                }
+            }
+
+            return Ok(if is_address {
+                DataTarget::RegisterAddress(register)
+            } else {
+                DataTarget::Register(register)
+            });
+        }
+
+        if let Ok(literal) = self.peek_literal() {
+            assert_eq!(literal, self.read_literal()?);
+
+            let mut is_address = false;
+            if let Some(next) = self.peek() {
+                if next.ty == TokenType::Star {
+                    self.skip();
+                    is_address = true;
+                }
+            }
+
+            return Ok(if is_address {
+                DataTarget::Address(Value::Literal(literal))
+            } else {
+                DataTarget::Immediate(Value::Literal(literal))
+            });
+        }
+
+        if self.peek_ident().is_ok() {
+            let ident = self.read_reference()?;
+
+            let mut is_address = false;
+            if let Some(next) = self.peek() {
+                if next.ty == TokenType::Star {
+                    self.skip();
+                    is_address = true;
+                }
+            }
+
+            return Ok(if is_address {
+                DataTarget::Address(Value::Identifier(ident))
+            } else {
+                DataTarget::Immediate(Value::Identifier(ident))
+            });
+        }
+
+        // TODO This error could be better for values that can *almost* be parsed, like a number can be parsed properly but fails due to size.
+        Err(CompileError {
+            message: "Invalid data target".to_owned(),
+            span: self.latest_span.clone(),
+        })
+    }
+
+    /// Reads comma separated operands up to the end of the line, the end of the block or a condition.
+    fn read_operands(&mut self) -> Result<Vec<Operand>, CompileError> {
+        let mut operands = Vec::new();
+
+        match self.peek() {
+            None => return Ok(operands),
+            Some(next)
+                if matches!(
+                    next.ty,
+                    TokenType::NewLine | TokenType::ClosingCurly | TokenType::Keyword
+                ) =>
+            {
+                return Ok(operands)
+            }
+            _ => {}
+        }
+
+        loop {
+            let start = self.peek_span();
+            let target = self.read_data_target()?;
+            operands.push(Operand {
+                zero_page: self.is_zero_page(&target),
+                target,
+                span: start.to(&self.latest_span),
+            });
+
+            match self.peek() {
+                Some(next) if next.ty == TokenType::Comma => self.skip(),
+                _ => return Ok(operands),
+            }
+        }
+    }
+
+    /// Reads an instruction and its operands, and encodes it using the matching form of the instruction set.
+    pub fn read_instruction_line(&mut self) {
+        let set = self.target.instruction_set();
+
+        let mnemonic_span = self.peek_span();
+        let mnemonic = try_return!(self, self.read_instruction()).to_lowercase();
+        if !isa::is_mnemonic(set, &mnemonic) {
+            let message = if isa::is_any_mnemonic(&mnemonic) {
+                format!(
+                    "'{}' isn't available on the {}",
+                    mnemonic,
+                    self.target.cpu_name()
+                )
+            } else {
+                format!("Unable to find mnemonic '{}'", mnemonic)
+            };
+
+            self.errors.push(CompileError {
+                message,
+                span: self.latest_span.clone(),
+            });
+            self.next_reset();
+            return;
+        }
+
+        let operands = try_return!(self, self.read_operands());
+        for operand in &operands {
+            let register = match &operand.target {
+                DataTarget::Register(register) | DataTarget::RegisterAddress(register) => *register,
+                DataTarget::Indexed(index_reg, _) => Register::Long(*index_reg),
+                DataTarget::IndexedAddress(_, index_reg) => Register::Short(*index_reg),
+                DataTarget::IndexedIndirect(_) => Register::Short(ShortRegister::X),
+                DataTarget::IndirectIndexed(_) => Register::Short(ShortRegister::Y),
+                _ => continue,
+            };
+
+            if !self.target.has_register(register) {
+                self.errors.push(CompileError {
+                    message: format!(
+                        "The {} has no {} register",
+                        self.target.cpu_name(),
+                        register
+                    ),
+                    span: operand.span.clone(),
+                });
+                self.next_reset();
+                return;
+            }
+        }
+        let site_span = mnemonic_span.to(&self.latest_span);
+
+        let condition_start = self.peek_span();
+        let condition = try_return!(self, self.read_condition())
+            .map(|condition| (condition, condition_start.to(&self.latest_span)));
+
+        let def = try_return!(
+            self,
+            find_form(set, &mnemonic, &operands, condition.clone(), &site_span)
+        );
+
+        if def.undocumented && !self.allow_undocumented {
+            self.errors.push(CompileError {
+                message: format!(
+                    "'{}' is an undocumented instruction, enable undocumented instructions with '@undocumented'",
+                    self.slice(&site_span)
+                ),
+                span: site_span,
+            });
+            self.next_reset();
+            return;
+        }
+
+        if let Some(reason) = self.target.missing(def) {
+            self.errors.push(CompileError {
+                message: format!(
+                    "'{}' isn't available on the {}, {}",
+                    self.slice(&site_span),
+                    self.target.cpu_name(),
+                    reason
+                ),
+                span: site_span,
+            });
+            self.next_reset();
+            return;
+        }
+
+        let mut codes = Vec::with_capacity(operands.len());
+        for (pattern, operand) in def.operands.iter().zip(&operands) {
+            let code = try_return!(
+                self,
+                pattern
+                    .code(&operand.target)
+                    .map_err(|message| CompileError {
+                        message,
+                        span: operand.span.clone(),
+                    })
+            );
+            codes.push(code);
+        }
+
+        let condition = condition.map(|(condition, _)| condition);
+        self.write_instruction(def, operands, codes, condition, site_span);
+    }
+
+    /// The size of the jump `write_jump` writes.
+    pub fn jump_size(&self, condition: Option<Condition>, short: bool) -> u16 {
+        match (self.target, condition) {
+            _ if short => 2,
+            // The opposite branch skips over an absolute jump.
+            (Target::MOS6502, Some(_)) => 5,
+            _ => 3,
+        }
+    }
+
+    /// Writes the jump of a structured block to the hidden label `label`, taken if `condition` holds.
+    /// Short jumps are relative, the caller checks they reach with `Target::has_relative_jump`.
+    pub fn write_jump(
+        &mut self,
+        label: &str,
+        condition: Option<(Condition, Span)>,
+        short: bool,
+        span: &Span,
+    ) -> Result<(), CompileError> {
+        let label = Value::Identifier(label.to_owned());
+
+        match (self.target, condition) {
+            (Target::MOS6502, Some((condition, _))) if short => {
+                self.write_generated(isa::branch(condition), Some(label), None, span)
+            }
+            (Target::MOS6502, Some((condition, _))) => {
+                let skip = Value::Literal(self.address.wrapping_add(5));
+                self.write_generated(isa::branch(condition.negate()), Some(skip), None, span)?;
+                self.write_generated("jmp", Some(label), None, span)
+            }
+            (Target::MOS6502, None) => self.write_generated("jmp", Some(label), None, span),
+            (_, condition) => {
+                let mnemonic = if short { "jr" } else { "jp" };
+                self.write_generated(mnemonic, Some(label), condition, span)
+            }
+        }
+    }
+
+    /// Writes an instruction generated for a structured block, with an immediate operand if there's a `value`.
+    pub fn write_generated(
+        &mut self,
+        mnemonic: &str,
+        value: Option<Value>,
+        condition: Option<(Condition, Span)>,
+        span: &Span,
+    ) -> Result<(), CompileError> {
+        let operands = value
+            .into_iter()
+            .map(|value| Operand {
+                target: DataTarget::Immediate(value),
+                span: span.clone(),
+                zero_page: false,
+            })
+            .collect::<Vec<_>>();
+
+        let set = self.target.instruction_set();
+        let def = find_form(set, mnemonic, &operands, condition.clone(), span)?;
+        let codes = def
+            .operands
+            .iter()
+            .zip(&operands)
+            .map(|(pattern, operand)| pattern.code(&operand.target))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|message| CompileError {
+                message,
+                span: span.clone(),
+            })?;
+
+        let condition = condition.map(|(condition, _)| condition);
+        self.write_instruction(def, operands, codes, condition, span.clone());
+        Ok(())
+    }
+
+    /// Queues the encoding of `def`, values are resolved once every declaration is known.
+    fn write_instruction(
+        &mut self,
+        def: &'static InstructionDef,
+        operands: Vec<Operand>,
+        codes: Vec<u8>,
+        condition: Option<Condition>,
+        site_span: Span,
+    ) {
+        self.last_instruction = Some(def);
+
+        let address = self.address;
+        let size = def.size();
+        let prefix = operands
+            .iter()
+            .find_map(|operand| operand.target.index_register())
+            .and_then(LongRegister::index_prefix);
+
+        self.write_dyn(size, move |ctx| {
+            let mut data = Vec::with_capacity(usize::from(size));
+
+            for enc in def.encoding {
+                match *enc {
+                    Enc::Byte(byte) => data.push(byte),
+                    Enc::Op(opcode, fields) => {
+                        let opcode = fields.iter().fold(opcode, |opcode, field| match *field {
+                            Field::Operand(i, shift) => opcode | codes[i] << shift,
+                            Field::Condition => {
+                                opcode | condition.map_or(0, Condition::code) << 3
+                            }
+                        });
+                        data.push(opcode);
+                    }
+                    Enc::IndexPrefix => data.push(prefix.unwrap_or(0)),
+                    Enc::Disp(i) => match &operands[i].target {
+                        DataTarget::Indexed(_, displacement) => {
+                            data.push(displacement.resolve(ctx))
+                        }
+                        _ => data.push(0),
+                    },
+                    Enc::Imm8(i) => {
+                        let operand = &operands[i];
+                        let value = operand
+                            .value()
+                            .and_then(|value| value.resolve(ctx, &operand.span))
+                            .unwrap_or(0);
+
+                        // High page addresses can be written in full, only the low byte is encoded.
+                        let value = match def.operands[i] {
+                            Pattern::HighAddr if value >= 0xFF00 => value - 0xFF00,
+                            _ => value,
+                        };
+
+                        if value > 0xFF {
+                            let message = if def.operands[i] == Pattern::HighAddr {
+                                format!(
+                                    "Address '${:04X}' isn't in the high page $FF00..$FFFF",
+                                    value
+                                )
+                            } else if def.operands[i] == Pattern::Port {
+                                format!("Port '${:04X}' doesn't fit into 8 bits", value)
+                            } else {
+                                format!(
+                                    "Number '{}' is too big to fit into {}",
+                                    value,
+                                    imm8_destination(&operands, i)
+                                )
+                            };
+
+                            ctx.errors.push(CompileError {
+                                message,
+                                span: operand.span.clone(),
+                            });
+                        }
+
+                        data.push(value as u8);
+                    }
+                    Enc::Imm16(i) => {
+                        let operand = &operands[i];
+                        let value = operand
+                            .value()
+                            .and_then(|value| value.resolve(ctx, &operand.span))
+                            .unwrap_or(0);
+                        data.extend(value.to_le_bytes());
+                    }
+                    Enc::Rel(i) => {
+                        let operand = &operands[i];
+                        let Some(target_address) = operand
+                            .value()
+                            .and_then(|value| value.resolve(ctx, &operand.span))
+                        else {
+                            data.push(0);
+                            continue;
+                        };
+
+                        // The displacement is relative to the end of the instruction.
+                        let displacement = target_address as i32 - (address as i32 + size as i32);
+                        let Ok(displacement) = i8::try_from(displacement) else {
+                            ctx.errors.push(CompileError {
+                                message: format!(
+                                    "'{}' at ${:04X} can't reach '{}' at ${:04X}, the displacement {} doesn't fit into -128..127",
+                                    def.mnemonic, address, operand.target, target_address, displacement
+                                ),
+                                span: site_span.clone(),
+                            });
+                            data.push(0);
+                            continue;
+                        };
+
+                        data.push(displacement as u8);
+                    }
+                }
+            }
+
+            data
+        });
+    }
+}
@@ -0,0 +1,333 @@
+use std::io::Cursor;
+
+use crate::{
+    sources::resolve_import,
+    tokenizer::{tokenize, TokenType, TokenizerResult},
+    CompileError, FileId, Span,
+};
+
+use super::{Compiler, SymbolKind};
+
+impl<'a> Compiler<'a> {
+    /// The full name of `name` declared in the current module, like `vdp::init`.
+    pub fn qualify(&self, name: &str) -> String {
+        if self.namespace.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{}::{}", self.namespace, name)
+        }
+    }
+
+    /// Reads the name of a new declaration, which belongs to the current module.
+    pub fn read_declared_name(&mut self) -> Result<String, CompileError> {
+        let name = self.read_ident()?.to_owned();
+        Ok(self.qualify(&name))
+    }
+
+    /// Reads `import "drivers/vdp.zir"`, compiling the module where it's first imported.
+    /// Its declarations are named after the file, like `vdp::init`.
+    pub fn read_import(&mut self) {
+        self.skip();
+        let import_span = self.latest_span.clone();
+        let import = try_return!(self, self.read_string()).to_owned();
+        let span = import_span.to(&self.latest_span);
+
+        let Some(from) = self.files[span.file].path else {
+            self.errors.push(CompileError {
+                message: format!(
+                    "Can't import '{}' from a single file, load the files with 'Sources'",
+                    import
+                ),
+                span,
+            });
+            return;
+        };
+
+        // Files that couldn't be loaded were already reported by `Sources`.
+        let path = resolve_import(from, &import);
+        let Some(file) = self.files.iter().position(|file| file.path == Some(&path)) else {
+            return;
+        };
+
+        let namespace = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        // `vdp::init` only works if the module name reads as a name of its own.
+        let reason = match tokenize(&mut Cursor::new(namespace.as_bytes())) {
+            Ok(TokenizerResult { tokens, .. }) => match tokens.as_slice() {
+                [token] if token.span.pos == (0..namespace.len()) => match token.ty {
+                    TokenType::Identifier => None,
+                    TokenType::Register => Some(", it's a register"),
+                    TokenType::Instruction => Some(", it's a mnemonic"),
+                    TokenType::Keyword | TokenType::LabelSpecifier | TokenType::DataDeclaration => {
+                        Some(", it's a keyword")
+                    }
+                    _ => Some(""),
+                },
+                _ => Some(""),
+            },
+            Err(_) => Some(""),
+        };
+        if let Some(reason) = reason {
+            self.errors.push(CompileError {
+                message: format!("'{}' can't be used as a module name{}", namespace, reason),
+                span,
+            });
+            return;
+        }
+
+        match self.modules.get(&namespace) {
+            Some(existing) if *existing == file => return,
+            Some(existing) => {
+                self.errors.push(CompileError {
+                    message: format!(
+                        "Module '{}' is already imported from '{}'",
+                        namespace,
+                        self.files[*existing].path.unwrap().display()
+                    ),
+                    span,
+                });
+                return;
+            }
+            None => {}
+        }
+        self.modules.insert(namespace.clone(), file);
+
+        // Import cycles were already reported by `Sources`.
+        if !self.compiled_files.insert(file) {
+            return;
+        }
+
+        let outer_namespace = std::mem::replace(&mut self.namespace, namespace);
+        self.compile_file(file);
+        self.namespace = outer_namespace;
+    }
+
+    /// Reads `@include("file.zir")` after the directive, compiling the file in place as if it was part of this one.
+    /// A file already included into the current module is skipped, so its declarations aren't repeated.
+    pub fn read_include_pragma(&mut self, at_span: Span) {
+        let _ = try_return!(self, self.read_token_with_type(TokenType::OpeningParen));
+        let include = try_return!(self, self.read_string()).to_owned();
+        let _ = try_return!(self, self.read_token_with_type(TokenType::ClosingParen));
+        let span = at_span.to(&self.latest_span);
+
+        let Some(from) = self.files[span.file].path else {
+            self.errors.push(CompileError {
+                message: format!(
+                    "Can't include '{}' from a single file, load the files with 'Sources'",
+                    include
+                ),
+                span,
+            });
+            return;
+        };
+
+        // Missing files and include cycles were already reported by `Sources`.
+        let path = resolve_import(from, &include);
+        let Some(file) = self.files.iter().position(|file| file.path == Some(&path)) else {
+            return;
+        };
+        if !self.included_files.insert((file, self.namespace.clone())) {
+            return;
+        }
+
+        self.compile_file(file);
+    }
+
+    /// Reads `@incbin(font, "font.bin", offset, length)` after the directive, embedding the file at the current address.
+    /// The name is optional, without a length everything after the offset is embedded.
+    pub fn read_incbin_pragma(&mut self, at_span: Span) {
+        let _ = try_return!(self, self.read_token_with_type(TokenType::OpeningParen));
+        let name = match self.peek() {
+            Some(token) if token.ty == TokenType::Identifier => {
+                let name = try_return!(self, self.read_declared_name());
+                let _ = try_return!(self, self.read_token_with_type(TokenType::Comma));
+                Some(name)
+            }
+            _ => None,
+        };
+        let incbin = try_return!(self, self.read_string()).to_owned();
+        let span = at_span.to(&self.latest_span);
+
+        let mut offset = (0, span.clone());
+        let mut length = None;
+        if self
+            .peek()
+            .is_some_and(|token| token.ty == TokenType::Comma)
+        {
+            self.skip();
+            offset = (
+                try_return!(self, self.read_literal()),
+                self.latest_span.clone(),
+            );
+
+            if self
+                .peek()
+                .is_some_and(|token| token.ty == TokenType::Comma)
+            {
+                self.skip();
+                length = Some((
+                    try_return!(self, self.read_literal()),
+                    self.latest_span.clone(),
+                ));
+            }
+        }
+        let _ = try_return!(self, self.read_token_with_type(TokenType::ClosingParen));
+
+        let Some(from) = self.files[span.file].path else {
+            self.errors.push(CompileError {
+                message: format!(
+                    "Can't embed '{}' from a single file, load the files with 'Sources'",
+                    incbin
+                ),
+                span,
+            });
+            return;
+        };
+
+        // Files that couldn't be read were already reported by `Sources`.
+        let path = resolve_import(from, &incbin);
+        let Some(data) = self
+            .binaries
+            .iter()
+            .find(|(binary, _)| *binary == path)
+            .map(|(_, data)| *data)
+        else {
+            return;
+        };
+
+        let (offset, offset_span) = offset;
+        let start = usize::from(offset);
+        if start > data.len() {
+            self.errors.push(CompileError {
+                message: format!(
+                    "Offset {} is past the end of '{}', which is {} bytes",
+                    offset,
+                    incbin,
+                    data.len()
+                ),
+                span: offset_span,
+            });
+            return;
+        }
+
+        let end = match length {
+            Some((length, length_span)) if start + usize::from(length) > data.len() => {
+                self.errors.push(CompileError {
+                    message: format!(
+                        "'{}' only has {} bytes after offset {}, not {}",
+                        incbin,
+                        data.len() - start,
+                        offset,
+                        length
+                    ),
+                    span: length_span,
+                });
+                return;
+            }
+            Some((length, _)) => start + usize::from(length),
+            None => data.len(),
+        };
+
+        let address = self.address;
+        let size = u16::try_from(end - start)
+            .ok()
+            .filter(|size| address.checked_add(*size).is_some());
+        let Some(size) = size else {
+            self.errors.push(CompileError {
+                message: format!(
+                    "'{}' is {} bytes, which doesn't fit into memory after ${:04X}",
+                    incbin,
+                    end - start,
+                    address
+                ),
+                span,
+            });
+            return;
+        };
+
+        // Unnamed data is reported by its file name.
+        let data_name = name.clone().unwrap_or_else(|| incbin.clone());
+        let range = address..address + size;
+        if let Some(existing) = self.reserve_area(&data_name, range.clone()) {
+            self.errors.push(CompileError {
+                message: format!("Data '{}' overlaps with '{}'", data_name, existing),
+                span: span.clone(),
+            });
+        }
+        self.check_in_rom(&format!("Data '{}'", data_name), range, span.clone());
+
+        if let Some(name) = name {
+            self.known_values.insert(name.clone(), address);
+            self.symbols.push((name.clone(), SymbolKind::Rom));
+            self.declare_size(&name, size, span.clone());
+            self.resolution(move |ctx| {
+                ctx.set(&name, address, &span);
+                true
+            });
+        }
+
+        let bytes = data[start..end].to_vec();
+        self.write_dyn(size, move |_| bytes);
+    }
+
+    /// Compiles every top level item of `file`, then continues where it left off.
+    fn compile_file(&mut self, file: FileId) {
+        let remaining_tokens =
+            std::mem::replace(&mut self.remaining_tokens, self.files[file].tokens);
+        let latest_span = self.latest_span.clone();
+
+        self.skip_line_sep();
+        while !self.remaining_tokens.is_empty() {
+            self.compile_remaining();
+            self.skip_line_sep();
+        }
+
+        self.latest_span = latest_span;
+        self.remaining_tokens = remaining_tokens;
+    }
+
+    /// Reads `pub` before a declaration, which lets other modules use it.
+    pub fn read_public_decl(&mut self) {
+        self.skip();
+        let pub_span = self.latest_span.clone();
+
+        let symbols = self.symbols.len();
+        match self.peek().map(|token| token.ty) {
+            Some(TokenType::LabelSpecifier) => self.read_label_block(),
+            Some(TokenType::DataDeclaration) => self.read_data_decl(),
+            _ => {
+                self.errors.push(CompileError {
+                    message: "'pub' has to be followed by a 'sub', 'def', 'rom' or 'var'"
+                        .to_owned(),
+                    span: pub_span,
+                });
+                self.next_reset();
+                return;
+            }
+        }
+
+        for (name, _) in &self.symbols[symbols..] {
+            self.exports.insert(name.clone());
+        }
+    }
+
+    /// Checks that names used from other modules are `pub`, unknown names are reported by the resolution.
+    pub fn check_references(&mut self) {
+        for reference in std::mem::take(&mut self.references) {
+            let declared = self.symbols.iter().any(|(name, _)| *name == reference.name);
+
+            if declared && !self.exports.contains(&reference.name) {
+                self.errors.push(CompileError {
+                    message: format!(
+                        "'{}' isn't public, declare it with 'pub' to use it from another module",
+                        reference.name
+                    ),
+                    span: reference.span,
+                });
+            }
+        }
+    }
+}
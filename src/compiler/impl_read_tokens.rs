@@ -1,30 +1,139 @@
 use crate::{tokenizer::TokenType, CompileError};
 
 use super::{
-    types::{LongRegister, Register, ShortRegister},
-    Compiler,
+    isa::Target,
+    size_name,
+    types::{Condition, LongRegister, Register, ShortRegister, Value},
+    Compiler, ForeignReference,
 };
 
 impl<'a> Compiler<'a> {
     pub fn read_ident(&mut self) -> Result<&str, CompileError> {
         let ident_token = self.read_token_with_type(TokenType::Identifier)?;
-        Ok(ident_token.span.slice(self.text))
+        Ok(self.slice(&ident_token.span))
     }
 
     pub fn peek_ident(&mut self) -> Result<&str, CompileError> {
         let ident_token = self.peek_token_with_type(TokenType::Identifier)?;
-        Ok(ident_token.span.slice(self.text))
+        Ok(self.slice(&ident_token.span))
     }
 
-    pub fn read_register(&mut self) -> Result<Register, CompileError> {
-        let register = self.peek_register()?;
+    /// Reads the name of a declaration, like `init` in the current module or `vdp::init` in another one.
+    /// `sizeof(font)` names the size of a subroutine or data, see `declare_size`.
+    pub fn read_reference(&mut self) -> Result<String, CompileError> {
+        let start = self.peek_span();
+        let name = self.read_ident()?.to_owned();
+
+        if name == "sizeof"
+            && self
+                .peek()
+                .is_some_and(|token| token.ty == TokenType::OpeningParen)
+        {
+            self.skip();
+            let sized = self.read_reference()?;
+            self.read_token_with_type(TokenType::ClosingParen)?;
+            return Ok(size_name(&sized));
+        }
+
+        let separator = self
+            .remaining_tokens
+            .get(..2)
+            .is_some_and(|tokens| tokens.iter().all(|token| token.ty == TokenType::Colon));
+        if !separator {
+            return Ok(self.qualify(&name));
+        }
+
+        self.skip();
         self.skip();
+        let member = self.read_ident()?;
+        let qualified = format!("{}::{}", name, member);
+
+        if name != self.namespace {
+            self.references.push(ForeignReference {
+                name: qualified.clone(),
+                span: start.to(&self.latest_span),
+            });
+        }
+
+        Ok(qualified)
+    }
+
+    /// Reads a register or an alias of one, registers with an alias can't be named directly.
+    pub fn read_register(&mut self) -> Result<Register, CompileError> {
+        if let Some(register) = self.peek_alias() {
+            self.skip();
+            return Ok(register);
+        }
+
+        let register = self.read_register_name()?;
+        if let Some(alias) = self
+            .aliases
+            .iter()
+            .find(|alias| alias.register.overlaps(register))
+        {
+            self.errors.push(CompileError {
+                message: format!(
+                    "'{}' can't be used directly while '{}' aliases {}",
+                    register, alias.name, alias.register
+                ),
+                span: self.latest_span.clone(),
+            });
+        }
+
         Ok(register)
     }
 
+    /// Peeks a register or an alias of one.
     pub fn peek_register(&mut self) -> Result<Register, CompileError> {
+        match self.peek_alias() {
+            Some(register) => Ok(register),
+            None => self.peek_register_name(),
+        }
+    }
+
+    /// The register bound to the next token, if it's the name of an alias.
+    fn peek_alias(&mut self) -> Option<Register> {
+        let token = self.peek()?;
+        if token.ty != TokenType::Identifier {
+            return None;
+        }
+
+        let name = self.slice(&token.span);
+        self.aliases
+            .iter()
+            .rev()
+            .find(|alias| alias.name == name)
+            .map(|alias| alias.register)
+    }
+
+    /// Reads a register by its own name, ignoring aliases.
+    pub fn read_register_name(&mut self) -> Result<Register, CompileError> {
+        let register = self.peek_register_name()?;
+        self.skip();
+        Ok(register)
+    }
+
+    /// X and Y are only registers on the 6502, on other targets they're names like any other.
+    pub fn peek_register_name(&mut self) -> Result<Register, CompileError> {
+        if self.target == Target::MOS6502 {
+            if let Some(token) = self
+                .peek()
+                .filter(|token| token.ty == TokenType::Identifier)
+            {
+                let register = match self.slice(&token.span) {
+                    "X" => Some(Register::Short(ShortRegister::X)),
+                    "Y" => Some(Register::Short(ShortRegister::Y)),
+                    _ => None,
+                };
+                if let Some(register) = register {
+                    self.latest_span = token.span;
+                    return Ok(register);
+                }
+            }
+        }
+
         let ident_token = self.peek_token_with_type(TokenType::Register)?;
-        Ok(match ident_token.span.slice(self.text) {
+        Ok(match self.slice(&ident_token.span) {
             "A" => Register::Short(ShortRegister::A),
             "B" => Register::Short(ShortRegister::B),
             "C" => Register::Short(ShortRegister::C),
@@ -35,7 +144,12 @@ impl<'a> Compiler<'a> {
             "L" => Register::Short(ShortRegister::L),
             "I" => Register::Short(ShortRegister::I),
             "R" => Register::Short(ShortRegister::R),
+            "IXH" => Register::Short(ShortRegister::IXH),
+            "IXL" => Register::Short(ShortRegister::IXL),
+            "IYH" => Register::Short(ShortRegister::IYH),
+            "IYL" => Register::Short(ShortRegister::IYL),
             "AF" => Register::Long(LongRegister::AF),
+            "AF'" => Register::Long(LongRegister::AFShadow),
             "BC" => Register::Long(LongRegister::BC),
             "DE" => Register::Long(LongRegister::DE),
             "HL" => Register::Long(LongRegister::HL),
@@ -43,13 +157,127 @@ impl<'a> Compiler<'a> {
             "SP" => Register::Long(LongRegister::SP),
             "IX" => Register::Long(LongRegister::IX),
             "IY" => Register::Long(LongRegister::IY),
-            _ => panic!("Unimplemented register"),
+            name => {
+                return Err(CompileError {
+                    message: format!("Unknown register '{}'", name),
+                    span: ident_token.span,
+                })
+            }
         })
     }
 
+    pub fn peek_keyword(&mut self, keyword: &str) -> bool {
+        matches!(
+            self.peek(),
+            Some(token) if token.ty == TokenType::Keyword && self.slice(&token.span) == keyword
+        )
+    }
+
+    pub fn skip_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek_keyword(keyword);
+        if found {
+            self.skip();
+        }
+        found
+    }
+
+    /// Reads an optional condition postfix like `if Zero` or `if not(Carry)`.
+    pub fn read_condition(&mut self) -> Result<Option<Condition>, CompileError> {
+        if !self.skip_keyword("if") {
+            return Ok(None);
+        }
+
+        self.read_flag_test().map(Some)
+    }
+
+    /// Reads a condition like `Zero` or `not(Carry)`.
+    pub fn read_flag_test(&mut self) -> Result<Condition, CompileError> {
+        let negated = self.skip_keyword("not");
+        if negated {
+            self.read_token_with_type(TokenType::OpeningParen)?;
+        }
+
+        let name_token = self.read_token_with_type(TokenType::Identifier)?;
+        let name = self.slice(&name_token.span);
+        let Some(condition) = Condition::from_name(name) else {
+            let names = Condition::NAMES
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>();
+
+            return Err(CompileError {
+                message: format!(
+                    "Unknown condition '{}', expected one of {}",
+                    name,
+                    names.join(", ")
+                ),
+                span: name_token.span,
+            });
+        };
+
+        if name == "Overflow" && self.target == Target::I8080 {
+            return Err(CompileError {
+                message: "The 8080 has no overflow flag, P/V always holds the parity, use ParityEven instead".to_owned(),
+                span: name_token.span,
+            });
+        }
+
+        if name.starts_with("Parity") && self.target == Target::MOS6502 {
+            return Err(CompileError {
+                message: "The 6502 has no parity flag, only Overflow".to_owned(),
+                span: name_token.span,
+            });
+        }
+
+        if negated {
+            self.read_token_with_type(TokenType::ClosingParen)?;
+            return Ok(condition.negate());
+        }
+
+        Ok(condition)
+    }
+
     pub fn read_instruction(&mut self) -> Result<&str, CompileError> {
+        // `sub` doubles as a label specifier, but inside a block it can only be the mnemonic.
+        // Mnemonics the Z80 doesn't have are lexed as identifiers, they're checked against the target by the caller.
+        if let Some(token) = self.peek() {
+            let is_sub = token.ty == TokenType::LabelSpecifier && self.slice(&token.span) == "sub";
+            if is_sub || token.ty == TokenType::Identifier {
+                self.skip();
+                return Ok(self.slice(&token.span));
+            }
+        }
+
         let inst_token = self.read_token_with_type(TokenType::Instruction)?;
-        Ok(inst_token.span.slice(self.text))
+        Ok(self.slice(&inst_token.span))
+    }
+
+    /// Reads a literal or the name of a declaration.
+    pub fn read_value(&mut self) -> Result<Value, CompileError> {
+        if let Ok(literal) = self.peek_literal() {
+            assert_eq!(literal, self.read_literal()?);
+            return Ok(Value::Literal(literal));
+        }
+
+        Ok(Value::Identifier(self.read_reference()?))
+    }
+
+    /// Skips a `*` if there is one, returning whether there was.
+    pub fn skip_star(&mut self) -> bool {
+        match self.peek() {
+            Some(token) if token.ty == TokenType::Star => {
+                self.skip();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Reads a string, without its quotes.
+    pub fn read_string(&mut self) -> Result<&str, CompileError> {
+        let string_token = self.read_token_with_type(TokenType::String)?;
+        let text = self.slice(&string_token.span);
+        Ok(&text[1..text.len() - 1])
     }
 
     pub fn read_literal(&mut self) -> Result<u16, CompileError> {
@@ -60,14 +288,17 @@ impl<'a> Compiler<'a> {
 
     pub fn peek_literal(&mut self) -> Result<u16, CompileError> {
         let Some(token) = self.peek() else {
-            return Err(CompileError { message: "Expected identifier, found EOF".to_owned(), span: self.latest_span.clone() });
+            return Err(CompileError {
+                message: "Expected identifier, found EOF".to_owned(),
+                span: self.latest_span.clone(),
+            });
         };
 
         self.latest_span = token.span.clone();
 
         let value = match token.ty {
             TokenType::HexNumber => {
-                let text = token.span.slice(self.text);
+                let text = self.slice(&token.span);
                 let text = &text[1..];
                 i32::from_str_radix(text, 16).map_err(|e| CompileError {
                     message: e.to_string(),
@@ -75,8 +306,8 @@ impl<'a> Compiler<'a> {
                 })?
             }
             TokenType::DecNumber => {
-                let text = token.span.slice(self.text);
-                i32::from_str_radix(text, 10).map_err(|e| CompileError {
+                let text = self.slice(&token.span);
+                text.parse::<i32>().map_err(|e| CompileError {
                     message: e.to_string(),
                     span: token.span.clone(),
                 })?
@@ -1,226 +1,716 @@
-use crate::{tokenizer::TokenType, CompileError, Span};
+use crate::{
+    tokenizer::{Token, TokenType},
+    CompileError, Span,
+};
 
 use super::{
-    types::{DataTarget, Register, ShortRegister},
-    Compiler,
+    cartridge,
+    isa::Target,
+    types::{Condition, Register, ShortRegister, Value},
+    Alias, Compiler, Fallthrough, LoopLabels, MeasuredBlock, Region, RegionKind, SymbolKind,
 };
 
-macro_rules! try_return {
-    ($self:expr, $call:expr) => {
-        match $call {
-            Ok(v) => v,
-            Err(e) => {
-                $self.errors.push(e);
-                $self.next_reset();
-                return;
+/// How execution leaves the end of a block.
+#[derive(Clone)]
+pub enum BlockEnd {
+    /// The last instruction can continue past the end of the block.
+    Open,
+    /// The last instruction is an unconditional jump, return or halt.
+    Terminated,
+    /// The block ends with `fallthrough`, continuing into whatever follows it in memory.
+    Fallthrough(Span),
+}
+
+impl<'a> Compiler<'a> {
+    pub fn read_block(&mut self) -> BlockEnd {
+        if let Err(e) = self.read_token_with_type(TokenType::OpeningCurly) {
+            self.errors.push(e);
+            self.next_reset();
+            return BlockEnd::Terminated;
+        }
+
+        self.skip_line_sep();
+
+        let mut end = BlockEnd::Open;
+        while let Some(peek_token) = self.peek() {
+            if peek_token.ty == TokenType::ClosingCurly {
+                self.skip();
+                break;
+            }
+
+            if let BlockEnd::Fallthrough(span) = &end {
+                self.errors.push(CompileError {
+                    message: "'fallthrough' has to be the last line of a block".to_owned(),
+                    span: span.clone(),
+                });
             }
+
+            if self.skip_keyword("fallthrough") {
+                end = BlockEnd::Fallthrough(self.latest_span.clone());
+                self.skip_line_sep();
+                continue;
+            }
+
+            if self.skip_keyword("using") {
+                end = self.read_using_block();
+                self.skip_line_sep();
+                continue;
+            }
+
+            if self.peek_keyword("if") {
+                end = self.read_if_block();
+                self.skip_line_sep();
+                continue;
+            }
+
+            if ["loop", "while", "repeat"]
+                .iter()
+                .any(|keyword| self.peek_keyword(keyword))
+            {
+                end = self.read_loop();
+                self.skip_line_sep();
+                continue;
+            }
+
+            if self.peek_keyword("break") || self.peek_keyword("continue") {
+                end = self.read_loop_jump();
+                self.skip_line_sep();
+                continue;
+            }
+
+            if self.skip_keyword("else") {
+                self.errors.push(CompileError {
+                    message: "'else' has to follow the '}' of an 'if' block".to_owned(),
+                    span: self.latest_span.clone(),
+                });
+                end = BlockEnd::Terminated;
+                self.skip_line_sep();
+                self.read_block();
+                self.skip_line_sep();
+                continue;
+            }
+
+            let error_count = self.errors.len();
+            self.last_instruction = None;
+            self.read_instruction_line();
+
+            // A line that failed to compile already has an error, so it isn't reported again as an open end.
+            end = match self.last_instruction {
+                _ if self.errors.len() > error_count => BlockEnd::Terminated,
+                Some(def) if def.ends_block() => BlockEnd::Terminated,
+                _ => BlockEnd::Open,
+            };
+            self.skip_line_sep();
         }
-    };
-}
 
-macro_rules! try_into_u8 {
-    ($self:expr, $register:expr, $imm:expr, $span:expr) => {{
-        if $imm > 0xFF {
-            $self.errors.push(CompileError {
-                message: format!(
-                    "Number '{}' is too big to fit into the {:?} register",
-                    $imm, $register
-                ),
-                span: $span.clone(),
-            });
-            $self.next_reset();
-            return;
+        end
+    }
+
+    /// Compiles the block at `tokens` without keeping anything, to find its size and how it ends.
+    fn measure_block_at(&mut self, tokens: &'a [Token]) -> MeasuredBlock<'a> {
+        let file = tokens.first().map_or(0, |token| token.span.file);
+        let key = (self.namespace.clone(), file, tokens.len());
+        if let Some(measured) = self.measured_blocks.get(&key) {
+            return measured.clone();
         }
-        $imm as u8
-    }};
-}
 
-impl<'a> Compiler<'a> {
-    pub fn read_data_target(&mut self) -> Result<DataTarget, CompileError> {
-        if let Ok(register) = self.peek_register() {
-            assert_eq!(register, self.read_register()?);
-            return Ok(DataTarget::Register(register));
+        let checkpoint = self.checkpoint();
+        self.remaining_tokens = tokens;
+        let start = self.address;
+        let end = self.read_block();
+        let measured = MeasuredBlock {
+            size: self.address.wrapping_sub(start),
+            end,
+            rest: self.remaining_tokens,
+        };
+        self.rewind(checkpoint);
+
+        self.measured_blocks.insert(key, measured.clone());
+        measured
+    }
+
+    /// Finds the block of an `else` following `tokens`, without reading it.
+    fn find_else_block(&mut self, tokens: &'a [Token]) -> Option<&'a [Token]> {
+        let checkpoint = self.checkpoint();
+        self.remaining_tokens = tokens;
+        self.skip_line_sep();
+        let found = self.skip_keyword("else");
+        self.skip_line_sep();
+        let block = found.then_some(self.remaining_tokens);
+        self.rewind(checkpoint);
+        block
+    }
+
+    /// Declares a label that can't be written in the source, for the jumps of structured blocks.
+    fn hidden_label(&mut self, name: &str) {
+        let address = self.address;
+        let name = name.to_owned();
+        let span = self.latest_span.clone();
+        self.resolution(move |ctx| {
+            ctx.set(&name, address, &span);
+            true
+        });
+    }
+
+    /// Whether a jump over `distance` bytes can be relative.
+    fn is_short_jump(&self, condition: Option<Condition>, distance: u16) -> bool {
+        self.target.has_relative_jump(condition) && distance <= 127
+    }
+
+    /// Reads the body of an `if` or `else`, which continues into whatever follows the `if` block.
+    fn read_branch(&mut self) -> BlockEnd {
+        match self.read_block() {
+            BlockEnd::Fallthrough(span) => {
+                self.errors.push(CompileError {
+                    message: "'fallthrough' can only end a subroutine".to_owned(),
+                    span,
+                });
+                BlockEnd::Terminated
+            }
+            end => end,
         }
+    }
 
-        if let Ok(literal) = self.peek_literal() {
-            assert_eq!(literal, self.read_literal()?);
+    /// Reads `if Zero { ... } else { ... }`, jumping over the first block if the condition doesn't hold.
+    /// Both blocks are measured first, so the jumps can be `jr` when they reach.
+    fn read_if_block(&mut self) -> BlockEnd {
+        let if_span = self.peek_span();
+        let start = self.remaining_tokens;
+        let condition = match self.read_condition() {
+            Ok(condition) => condition.expect("'if' was peeked"),
+            Err(e) => {
+                self.errors.push(e);
 
-            let mut is_address = false;
-            if let Some(next) = self.peek() {
-                if next.ty == TokenType::Star {
-                    self.skip();
-                    is_address = true;
+                self.skip_to_block(start);
+                self.read_block();
+                if self.find_else_block(self.remaining_tokens).is_some() {
+                    self.skip_line_sep();
+                    self.skip_keyword("else");
+                    self.skip_line_sep();
+                    self.read_block();
                 }
+                return BlockEnd::Terminated;
             }
+        };
+        let condition_span = if_span.to(&self.latest_span);
 
-            return Ok(if is_address {
-                DataTarget::Address(literal)
-            } else {
-                DataTarget::Immediate(literal)
-            });
+        let label = self.qualify(&format!("if@{}:{}", if_span.file, if_span.pos.start));
+        let else_label = format!("{}.else", label);
+        let end_label = format!("{}.end", label);
+
+        self.skip_line_sep();
+        let then_block = self.measure_block_at(self.remaining_tokens);
+        let else_block = self
+            .find_else_block(then_block.rest)
+            .map(|tokens| self.measure_block_at(tokens));
+
+        // A first block that can continue has to jump over the `else` block.
+        let else_jump = match (&else_block, &then_block.end) {
+            (Some(else_block), BlockEnd::Open) => Some(self.is_short_jump(None, else_block.size)),
+            _ => None,
+        };
+        let else_jump_size = else_jump.map_or(0, |short| self.jump_size(None, short));
+
+        let skip_condition = condition.negate();
+        let skip_distance = then_block.size.wrapping_add(else_jump_size);
+        let short = self.is_short_jump(Some(skip_condition), skip_distance);
+        let skip_label = if else_block.is_some() {
+            &else_label
+        } else {
+            &end_label
+        };
+
+        if let Err(e) = self.write_jump(
+            skip_label,
+            Some((skip_condition, condition_span.clone())),
+            short,
+            &condition_span,
+        ) {
+            self.errors.push(e);
         }
 
-        if let Ok(ident) = self.peek_ident() {
-            let ident = ident.to_owned();
-            assert_eq!(ident, self.read_ident()?);
+        let then_end = self.read_branch();
 
-            let mut is_address = false;
-            if let Some(next) = self.peek() {
-                if next.ty == TokenType::Star {
-                    self.skip();
-                    is_address = true;
-                }
+        if else_block.is_none() {
+            self.hidden_label(&end_label);
+            return BlockEnd::Open;
+        }
+
+        if let Some(short) = else_jump {
+            if let Err(e) = self.write_jump(&end_label, None, short, &if_span) {
+                self.errors.push(e);
             }
+        }
 
-            return Ok(if is_address {
-                DataTarget::IdentifierAddress(ident)
-            } else {
-                DataTarget::IdentifierImmediate(ident)
+        self.skip_line_sep();
+        self.skip_keyword("else");
+        self.skip_line_sep();
+        self.hidden_label(&else_label);
+        let else_end = self.read_branch();
+        self.hidden_label(&end_label);
+
+        match (then_end, else_end) {
+            (BlockEnd::Terminated, BlockEnd::Terminated) => BlockEnd::Terminated,
+            _ => BlockEnd::Open,
+        }
+    }
+
+    /// Skips from `start` to the `{` of a block after a malformed header, so the block is still read as a whole
+    /// and its lines aren't mistaken for the rest of the outer block. The header may have failed on the `{` itself.
+    fn skip_to_block(&mut self, start: &'a [Token]) {
+        let block = start
+            .iter()
+            .position(|token| matches!(token.ty, TokenType::OpeningCurly | TokenType::NewLine))
+            .unwrap_or(start.len());
+        self.remaining_tokens = &start[block..];
+        self.skip_line_sep();
+    }
+
+    /// Reports an error at `span` if a relative jump written at the current address can't reach `target`.
+    fn check_relative(&mut self, target: u16, what: &str, span: &Span) {
+        let displacement = i32::from(target) - (i32::from(self.address) + 2);
+        if i8::try_from(displacement).is_err() {
+            self.errors.push(CompileError {
+                message: format!(
+                    "{}, the displacement {} doesn't fit into -128..127",
+                    what, displacement
+                ),
+                span: span.clone(),
+            });
+        }
+    }
+
+    /// Measures the body of a loop with its labels in scope, so `break` and `continue` count towards its size.
+    fn measure_loop_body(&mut self, labels: LoopLabels) -> MeasuredBlock<'a> {
+        self.loops.push(labels);
+        let body = self.measure_block_at(self.remaining_tokens);
+        self.loops.pop();
+        body
+    }
+
+    /// Reads the body of a loop, returning whether a `break` can leave it.
+    fn read_loop_body(&mut self, labels: LoopLabels) -> bool {
+        self.loops.push(labels);
+        if let BlockEnd::Fallthrough(span) = self.read_block() {
+            self.errors.push(CompileError {
+                message: "'fallthrough' can only end a subroutine".to_owned(),
+                span,
             });
         }
+        self.loops.pop().is_some_and(|labels| labels.broken)
+    }
+
+    /// Reads `loop { ... }`, `while not(Zero) { ... }` or `repeat B { ... }`.
+    /// Their jumps are relative where the CPU has them, so a body too long for them is an error.
+    fn read_loop(&mut self) -> BlockEnd {
+        let keyword = self.next().unwrap();
+        let kind = self.slice(&keyword.span);
+        let label = self.qualify(&format!(
+            "{}@{}:{}",
+            kind, keyword.span.file, keyword.span.pos.start
+        ));
 
-        // TODO This error could be better for values that can *almost* be parsed, like a number can be parsed properly but fails due to size.
-        Err(CompileError {
-            message: "Invalid data target".to_owned(),
-            span: self.latest_span.clone(),
-        })
+        match kind {
+            "loop" => self.read_endless_loop(&label, keyword.span),
+            "while" => self.read_while_loop(&label, keyword.span),
+            _ => self.read_repeat_loop(&label, keyword.span),
+        }
     }
 
-    pub fn read_ld(&mut self) {
-        let start_span = self.latest_span.clone();
+    /// Reads `loop { ... }`, which only ends with a `break`.
+    fn read_endless_loop(&mut self, label: &str, span: Span) -> BlockEnd {
+        let start_label = format!("{}.start", label);
+        let end_label = format!("{}.end", label);
+        let short = self.target.has_relative_jump(None);
+
+        self.skip_line_sep();
+        let start = self.address;
+        self.hidden_label(&start_label);
 
-        let to = try_return!(self, self.read_data_target());
-        let _to_span = self.latest_span.clone();
+        let labels = |end| LoopLabels {
+            continue_label: start_label.clone(),
+            continue_address: Some(start),
+            break_label: end_label.clone(),
+            break_address: end,
+            broken: false,
+        };
+        let body = self.measure_loop_body(labels(None));
+        let end = start
+            .wrapping_add(body.size)
+            .wrapping_add(self.jump_size(None, short));
+        let broken = self.read_loop_body(labels(Some(end)));
 
-        if let Err(e) = self.read_token_with_type(TokenType::Comma) {
+        if short {
+            self.check_relative(
+                start,
+                "The 'loop' body is too long to jump back to its start",
+                &span,
+            );
+        }
+        if let Err(e) = self.write_jump(&start_label, None, short, &span) {
             self.errors.push(e);
-            self.next_reset();
-            return;
         }
+        self.hidden_label(&end_label);
 
-        let from = try_return!(self, self.read_data_target());
-        let from_span = self.latest_span.clone();
-
-        match (to.clone(), from.clone()) {
-            (DataTarget::Register(Register::Short(short_reg)), DataTarget::Immediate(imm)) => {
-                let imm = try_into_u8!(self, short_reg, imm, from_span);
-
-                self.write(move |_ctx| {
-                    [
-                        match short_reg {
-                            ShortRegister::A => 0x3E,
-                            ShortRegister::B => 0x06,
-                            ShortRegister::C => 0x0E,
-                            ShortRegister::D => 0x16,
-                            ShortRegister::E => 0x1E,
-                            ShortRegister::H => 0x26,
-                            ShortRegister::L => 0x2E,
-                            _ => unimplemented!(),
-                        },
-                        imm,
-                    ]
-                });
-            }
-            (
-                DataTarget::Address(addr),
-                DataTarget::Register(Register::Short(ShortRegister::A)),
-            ) => {
-                let [addr_low, addr_high] = addr.to_le_bytes();
-                self.write(move |_ctx| [0x32, addr_low, addr_high]);
+        if broken {
+            BlockEnd::Open
+        } else {
+            BlockEnd::Terminated
+        }
+    }
+
+    /// Reads `while not(Zero) { ... }`, testing the condition before every iteration.
+    fn read_while_loop(&mut self, label: &str, span: Span) -> BlockEnd {
+        let start_tokens = self.remaining_tokens;
+        let condition = match self.read_flag_test() {
+            Ok(condition) => condition,
+            Err(e) => {
+                self.errors.push(e);
+                self.skip_to_block(start_tokens);
+                self.read_block();
+                return BlockEnd::Terminated;
             }
-            (
-                DataTarget::IdentifierAddress(ident),
-                DataTarget::Register(Register::Short(ShortRegister::A)),
-            ) => {
-                self.write(move |ctx| {
-                    let addr = ctx.get(&ident).unwrap();
-                    let [addr_low, addr_high] = addr.to_le_bytes();
-                    [0x32, addr_low, addr_high]
-                });
+        };
+        let condition_span = span.to(&self.latest_span);
+
+        let start_label = format!("{}.start", label);
+        let end_label = format!("{}.end", label);
+        let exit_condition = condition.negate();
+        let exit_short = self.target.has_relative_jump(Some(exit_condition));
+        let back_short = self.target.has_relative_jump(None);
+
+        self.skip_line_sep();
+        let start = self.address;
+        self.hidden_label(&start_label);
+
+        let labels = |end| LoopLabels {
+            continue_label: start_label.clone(),
+            continue_address: Some(start),
+            break_label: end_label.clone(),
+            break_address: end,
+            broken: false,
+        };
+        let body = self.measure_loop_body(labels(None));
+        let end = start
+            .wrapping_add(self.jump_size(Some(exit_condition), exit_short))
+            .wrapping_add(body.size)
+            .wrapping_add(self.jump_size(None, back_short));
+
+        if exit_short {
+            self.check_relative(
+                end,
+                "The 'while' body is too long to jump past its end",
+                &span,
+            );
+        }
+        if let Err(e) = self.write_jump(
+            &end_label,
+            Some((exit_condition, condition_span.clone())),
+            exit_short,
+            &condition_span,
+        ) {
+            self.errors.push(e);
+        }
+
+        self.read_loop_body(labels(Some(end)));
+
+        if back_short {
+            self.check_relative(
+                start,
+                "The 'while' body is too long to jump back to its start",
+                &span,
+            );
+        }
+        if let Err(e) = self.write_jump(&start_label, None, back_short, &span) {
+            self.errors.push(e);
+        }
+        self.hidden_label(&end_label);
+
+        BlockEnd::Open
+    }
+
+    /// Reads `repeat B { ... }`, which counts B down to zero with `djnz`. The 6502 counts X or Y down instead.
+    fn read_repeat_loop(&mut self, label: &str, span: Span) -> BlockEnd {
+        let start_tokens = self.remaining_tokens;
+        let register = match self.read_register() {
+            Ok(register) => register,
+            Err(e) => {
+                self.errors.push(e);
+                self.skip_to_block(start_tokens);
+                self.read_block();
+                return BlockEnd::Terminated;
             }
-            _ => {
-                let end_span = self.latest_span.clone();
+        };
+
+        let counter = match (self.target, register) {
+            (Target::Z80, Register::Short(ShortRegister::B)) => Ok((None, "djnz")),
+            (Target::MOS6502, Register::Short(ShortRegister::X)) => Ok((Some("dex"), "bne")),
+            (Target::MOS6502, Register::Short(ShortRegister::Y)) => Ok((Some("dey"), "bne")),
+            (Target::Z80, _) => Err(format!(
+                "'repeat' counts down with 'djnz', which only uses B, found {}",
+                register
+            )),
+            (Target::MOS6502, _) => Err(format!(
+                "'repeat' counts down X or Y on the 6502, found {}",
+                register
+            )),
+            _ => Err(format!(
+                "'repeat' needs 'djnz', which the {} doesn't have",
+                self.target.cpu_name()
+            )),
+        };
+        let (step, branch) = match counter {
+            Ok(counter) => counter,
+            Err(message) => {
                 self.errors.push(CompileError {
-                    message: format!("'ld' isn't implemented for {:?} <- {:?}", to, from),
-                    // TODO make this span actually correct.
-                    span: Span {
-                        pos: start_span.pos.end..(end_span.pos.end + 1),
-                        col: start_span.col.end..(end_span.col.end + 1),
-                        line: start_span.line.end..(end_span.line.end + 1),
-                    },
+                    message,
+                    span: self.latest_span.clone(),
                 });
-                self.next_reset();
+                self.skip_to_block(self.remaining_tokens);
+                self.read_block();
+                return BlockEnd::Terminated;
+            }
+        };
+
+        let start_label = format!("{}.start", label);
+        let next_label = format!("{}.next", label);
+        let end_label = format!("{}.end", label);
+        let counter_size = if step.is_some() { 3 } else { 2 };
+
+        self.skip_line_sep();
+        let start = self.address;
+        self.hidden_label(&start_label);
+
+        let labels = |next, end| LoopLabels {
+            continue_label: next_label.clone(),
+            continue_address: next,
+            break_label: end_label.clone(),
+            break_address: end,
+            broken: false,
+        };
+        let body = self.measure_loop_body(labels(None, None));
+        let next = start.wrapping_add(body.size);
+        let end = next.wrapping_add(counter_size);
+        self.read_loop_body(labels(Some(next), Some(end)));
+
+        self.hidden_label(&next_label);
+        if let Some(step) = step {
+            if let Err(e) = self.write_generated(step, None, None, &span) {
+                self.errors.push(e);
             }
         }
-    }
+        self.check_relative(
+            start,
+            &format!(
+                "The 'repeat' body is too long for '{}' to jump back to its start",
+                branch
+            ),
+            &span,
+        );
+        let start_value = Value::Identifier(start_label);
+        if let Err(e) = self.write_generated(branch, Some(start_value), None, &span) {
+            self.errors.push(e);
+        }
+        self.hidden_label(&end_label);
 
-    // TODO support `if not(Zero)`-like post-fixes
-    pub fn read_jp(&mut self) {
-        let target = try_return!(self, self.read_ident()).to_owned();
-        self.write(move |ctx| {
-            let addr = ctx.get(&target).unwrap();
-            let [addr_low, addr_high] = addr.to_le_bytes();
-            [0xC3, addr_low, addr_high]
-        });
+        BlockEnd::Open
     }
 
-    pub fn read_instruction_line(&mut self) {
-        let inst = try_return!(self, self.read_instruction()).to_owned();
+    /// Reads `break` or `continue` in a loop, which can be conditional like `break if Zero`.
+    fn read_loop_jump(&mut self) -> BlockEnd {
+        let keyword = self.next().unwrap();
+        let name = self.slice(&keyword.span);
+        let span = keyword.span;
 
-        match inst.as_str() {
-            "ld" => self.read_ld(),
-            "jp" => self.read_jp(),
-            _ => {
-                self.errors.push(CompileError {
-                    message: format!("Unable to find mnemonic '{}'", inst),
-                    span: self.latest_span.clone(),
-                });
+        let condition_start = self.peek_span();
+        let condition = match self.read_condition() {
+            Ok(condition) => {
+                condition.map(|condition| (condition, condition_start.to(&self.latest_span)))
+            }
+            Err(e) => {
+                self.errors.push(e);
                 self.next_reset();
+                return BlockEnd::Terminated;
             }
+        };
+
+        let Some(labels) = self.loops.last_mut() else {
+            self.errors.push(CompileError {
+                message: format!("'{}' has to be inside a loop", name),
+                span,
+            });
+            self.next_reset();
+            return BlockEnd::Terminated;
+        };
+
+        let (label, address, what) = if name == "break" {
+            labels.broken = true;
+            (
+                labels.break_label.clone(),
+                labels.break_address,
+                "'break' can't reach the end of the loop",
+            )
+        } else {
+            (
+                labels.continue_label.clone(),
+                labels.continue_address,
+                "'continue' can't reach the next iteration of the loop",
+            )
+        };
+
+        let short = self
+            .target
+            .has_relative_jump(condition.as_ref().map(|(condition, _)| *condition));
+        if let Some(address) = address.filter(|_| short) {
+            self.check_relative(address, what, &span);
         }
+
+        let end = match condition {
+            Some(_) => BlockEnd::Open,
+            None => BlockEnd::Terminated,
+        };
+        if let Err(e) = self.write_jump(&label, condition, short, &span) {
+            self.errors.push(e);
+        }
+        end
     }
 
-    pub fn read_block(&mut self) {
-        try_return!(self, self.read_token_with_type(TokenType::OpeningCurly));
+    /// Reads `using (ptr = HL, count = B) { ... }`, the names stand for the registers inside the block.
+    fn read_using_block(&mut self) -> BlockEnd {
+        let outer_aliases = self.aliases.len();
 
-        self.skip_line_sep();
+        if let Err(e) = self.read_aliases() {
+            self.errors.push(e);
 
-        while let Some(peek_token) = self.peek() {
-            if peek_token.ty == TokenType::ClosingCurly {
+            // Still read the block, so its lines aren't mistaken for the rest of the outer block.
+            while let Some(token) = self.peek() {
+                if matches!(token.ty, TokenType::OpeningCurly | TokenType::NewLine) {
+                    break;
+                }
                 self.skip();
-                break;
             }
+        }
 
-            self.read_instruction_line();
-            self.skip_line_sep();
+        self.skip_line_sep();
+        let end = self.read_block();
+        self.aliases.truncate(outer_aliases);
+        end
+    }
+
+    /// Reads the aliases of a `using` block, adding each to the aliases in scope.
+    fn read_aliases(&mut self) -> Result<(), CompileError> {
+        self.read_token_with_type(TokenType::OpeningParen)?;
+
+        loop {
+            let name = self.read_ident()?.to_owned();
+            let name_span = self.latest_span.clone();
+            self.read_token_with_type(TokenType::Equals)?;
+            let register = self.read_register_name()?;
+
+            if let Some(existing) = self.aliases.iter().find(|alias| alias.name == name) {
+                return Err(CompileError {
+                    message: format!("'{}' already aliases {}", existing.name, existing.register),
+                    span: name_span,
+                });
+            }
+
+            if let Some(existing) = self
+                .aliases
+                .iter()
+                .find(|alias| alias.register.overlaps(register))
+            {
+                let message = if existing.register == register {
+                    format!("'{}' and '{}' both alias {}", existing.name, name, register)
+                } else {
+                    format!(
+                        "'{}' aliases {}, which overlaps with {} aliased by '{}'",
+                        name, register, existing.register, existing.name
+                    )
+                };
+
+                return Err(CompileError {
+                    message,
+                    span: self.latest_span.clone(),
+                });
+            }
+
+            self.aliases.push(Alias { name, register });
+
+            match self.next() {
+                Some(token) if token.ty == TokenType::Comma => {}
+                Some(token) if token.ty == TokenType::ClosingParen => return Ok(()),
+                _ => {
+                    return Err(CompileError {
+                        message: "Expected ',' or ')' after an alias".to_owned(),
+                        span: self.latest_span.clone(),
+                    })
+                }
+            }
         }
     }
 
     pub fn read_label_block(&mut self) {
         let specifier = self.next().unwrap();
-        match specifier.span.slice(self.text) {
+        match self.slice(&specifier.span) {
             "sub" => {
-                let name = try_return!(self, self.read_ident()).to_owned();
+                let name = try_return!(self, self.read_declared_name());
                 let name_span = self.latest_span.clone();
 
                 let start_address = self.address;
+                self.known_values.insert(name.clone(), start_address);
+                self.symbols.push((name.clone(), SymbolKind::Subroutine));
+
+                // The fallthrough of the previous subroutine is expected to land here.
+                if let Some(fallthrough) = self
+                    .fallthroughs
+                    .last_mut()
+                    .filter(|fallthrough| fallthrough.next.is_none())
+                {
+                    fallthrough.next = Some(name.clone());
+                }
+
                 self.resolution({
                     let name = name.clone();
+                    let span = name_span.clone();
                     move |ctx| {
-                        ctx.set(&name, start_address);
+                        ctx.set(&name, start_address, &span);
                         true
                     }
                 });
                 self.skip_line_sep();
-                self.read_block();
+                let end = self.read_block();
                 let end_address = self.address;
 
+                match end {
+                    BlockEnd::Open => self.errors.push(CompileError {
+                        message: format!(
+                            "Subroutine '{}' can fall off the end, end it with a jump, 'ret', 'hlt' or 'fallthrough'",
+                            name
+                        ),
+                        span: name_span.clone(),
+                    }),
+                    BlockEnd::Fallthrough(span) => self.fallthroughs.push(Fallthrough {
+                        name: name.clone(),
+                        span,
+                        address: end_address,
+                        next: None,
+                    }),
+                    BlockEnd::Terminated => {}
+                }
+
                 if let Some(existing) = self.reserve_area(&name, start_address..end_address) {
                     self.errors.push(CompileError {
                         message: format!("Subroutine '{}' overlaps with '{}'", name, existing),
-                        span: name_span,
+                        span: name_span.clone(),
                     });
                 }
+
+                self.check_in_rom(
+                    &format!("Subroutine '{}'", name),
+                    start_address..end_address,
+                    name_span.clone(),
+                );
+                self.declare_size(&name, end_address - start_address, name_span);
             }
             ty => {
                 self.errors.push(CompileError {
@@ -234,9 +724,10 @@ impl<'a> Compiler<'a> {
 
     pub fn read_data_decl(&mut self) {
         let tok = self.next().unwrap();
-        match tok.span.slice(self.text) {
+        match self.slice(&tok.span) {
             "def" => {
-                let name = try_return!(self, self.read_ident()).to_owned();
+                let name = try_return!(self, self.read_declared_name());
+                let name_span = self.latest_span.clone();
 
                 if let Err(e) = self.read_token_with_type(TokenType::Equals) {
                     self.errors.push(e);
@@ -247,13 +738,16 @@ impl<'a> Compiler<'a> {
                 // TODO more advanced expressions
                 let value = try_return!(self, self.read_literal());
 
+                self.known_values.insert(name.clone(), value);
+                self.symbols.push((name.clone(), SymbolKind::Definition));
                 self.resolution(move |ctx| {
-                    ctx.set(&name, value);
+                    ctx.set(&name, value, &name_span);
                     true
                 });
             }
             "rom" => {
-                let name = try_return!(self, self.read_ident()).to_owned();
+                let name = try_return!(self, self.read_declared_name());
+                let name_span = self.latest_span.clone();
 
                 if let Err(e) = self.read_token_with_type(TokenType::Colon) {
                     self.errors.push(e);
@@ -270,19 +764,41 @@ impl<'a> Compiler<'a> {
                 }
 
                 // TODO more advanced expressions
-                let value_ident = try_return!(self, self.read_ident()).to_owned();
+                let value_ident = try_return!(self, self.read_reference());
+                let value_span = self.latest_span.clone();
 
                 let current_address = self.address;
-                self.resolution(move |ctx| {
-                    ctx.set(&name, current_address);
-                    true
+                let data_name = name.clone();
+                self.known_values.insert(name.clone(), current_address);
+                self.symbols.push((name.clone(), SymbolKind::Rom));
+                self.declare_size(&name, size, name_span.clone());
+                self.resolution({
+                    let span = name_span.clone();
+                    move |ctx| {
+                        ctx.set(&name, current_address, &span);
+                        true
+                    }
                 });
 
                 match size {
-                    2 => self.write(move |ctx| {
-                        let value = ctx.get(&value_ident).unwrap();
-                        value.to_le_bytes()
-                    }),
+                    2 => {
+                        let range = current_address..current_address + size;
+                        if let Some(existing) = self.reserve_area(&data_name, range.clone()) {
+                            self.errors.push(CompileError {
+                                message: format!(
+                                    "Data '{}' overlaps with '{}'",
+                                    data_name, existing
+                                ),
+                                span: name_span.clone(),
+                            });
+                        }
+                        self.check_in_rom(&format!("Data '{}'", data_name), range, name_span);
+
+                        self.write(move |ctx| {
+                            let value = ctx.get_label(&value_ident, &value_span).unwrap_or(0);
+                            value.to_le_bytes()
+                        })
+                    }
                     _ => {
                         self.errors.push(CompileError {
                             message: format!("Invalid data size '{}', expected 2", size),
@@ -292,6 +808,7 @@ impl<'a> Compiler<'a> {
                     }
                 }
             }
+            "var" => self.read_var_decl(),
             ty => {
                 self.errors.push(CompileError {
                     message: format!("Unimplemented data declaration type '{}'", ty),
@@ -302,8 +819,69 @@ impl<'a> Compiler<'a> {
         }
     }
 
+    /// Reads `var name: size`, allocating `size` bytes of RAM for the variable.
+    fn read_var_decl(&mut self) {
+        let name = try_return!(self, self.read_declared_name());
+        let name_span = self.latest_span.clone();
+        let _ = try_return!(self, self.read_token_with_type(TokenType::Colon));
+        let size = try_return!(self, self.read_literal());
+        let size_span = self.latest_span.clone();
+
+        if size == 0 {
+            self.errors.push(CompileError {
+                message: format!("Variable '{}' has to be at least 1 byte", name),
+                span: size_span,
+            });
+            return;
+        }
+
+        let ram = self
+            .regions
+            .iter()
+            .filter(|region| region.kind == RegionKind::Ram)
+            .map(|region| region.range.clone())
+            .collect::<Vec<_>>();
+
+        if ram.is_empty() {
+            self.errors.push(CompileError {
+                message: format!(
+                    "Variable '{}' needs a RAM region, declare one with '@region(name, start, size, RAM)'",
+                    name
+                ),
+                span: name_span,
+            });
+            return;
+        }
+
+        // RAM regions are filled in the order they're declared.
+        let address = ram
+            .iter()
+            .find_map(|within| self.allocate(&name, within, size));
+        let Some(address) = address else {
+            self.errors.push(CompileError {
+                message: format!(
+                    "Variable '{}' doesn't fit into the free RAM, it needs {} byte{}",
+                    name,
+                    size,
+                    if size == 1 { "" } else { "s" }
+                ),
+                span: name_span,
+            });
+            return;
+        };
+
+        self.known_values.insert(name.clone(), address);
+        self.symbols
+            .push((name.clone(), SymbolKind::Variable(size)));
+        self.declare_size(&name, size, name_span.clone());
+        self.resolution(move |ctx| {
+            ctx.set(&name, address, &name_span);
+            true
+        });
+    }
+
     pub fn read_top_level_pragma(&mut self) {
-        let _ = self.read_token_with_type(TokenType::At).unwrap();
+        let at_span = self.read_token_with_type(TokenType::At).unwrap().span;
         let directive = try_return!(self, self.read_ident()).to_owned();
         match directive.as_str() {
             "origin" => {
@@ -312,6 +890,44 @@ impl<'a> Compiler<'a> {
                 self.set_address(new_address);
                 let _ = try_return!(self, self.read_token_with_type(TokenType::ClosingParen));
             }
+            "include" => self.read_include_pragma(at_span),
+            "incbin" => self.read_incbin_pragma(at_span),
+            "undocumented" => self.allow_undocumented = true,
+            "region" => self.read_region_pragma(),
+            "cartridge" => self.read_cartridge_pragma(),
+            "target" => {
+                let directive_span = self.latest_span.clone();
+                let _ = try_return!(self, self.read_token_with_type(TokenType::OpeningParen));
+                let name = try_return!(self, self.read_ident()).to_owned();
+                let Some(target) = Target::from_name(&name) else {
+                    let names = Target::NAMES
+                        .iter()
+                        .map(|(name, _)| *name)
+                        .collect::<Vec<_>>();
+
+                    self.errors.push(CompileError {
+                        message: format!(
+                            "Unknown target '{}', expected one of {}",
+                            name,
+                            names.join(", ")
+                        ),
+                        span: self.latest_span.clone(),
+                    });
+                    self.next_reset();
+                    return;
+                };
+                let _ = try_return!(self, self.read_token_with_type(TokenType::ClosingParen));
+
+                // Code that was already compiled would silently be for the wrong CPU.
+                if self.has_written {
+                    self.errors.push(CompileError {
+                        message: "'@target' has to come before any code".to_owned(),
+                        span: directive_span,
+                    });
+                }
+
+                self.target = target;
+            }
             _ => {
                 self.errors.push(CompileError {
                     message: format!("Unknown top level directive '{}'", directive),
@@ -321,4 +937,126 @@ impl<'a> Compiler<'a> {
             }
         }
     }
+
+    /// Reads `@region(name, start, size, kind)`, declaring a memory region where code, data or variables go.
+    fn read_region_pragma(&mut self) {
+        let _ = try_return!(self, self.read_token_with_type(TokenType::OpeningParen));
+        let name = try_return!(self, self.read_ident()).to_owned();
+        let name_span = self.latest_span.clone();
+        let _ = try_return!(self, self.read_token_with_type(TokenType::Comma));
+        let start = try_return!(self, self.read_literal());
+        let _ = try_return!(self, self.read_token_with_type(TokenType::Comma));
+        let size = try_return!(self, self.read_literal());
+        let size_span = self.latest_span.clone();
+        let _ = try_return!(self, self.read_token_with_type(TokenType::Comma));
+        // The tokenizer reads `ROM` as the `rom` declaration, so it's accepted along with identifiers.
+        let kind_token = match self.peek() {
+            Some(token) if token.ty == TokenType::DataDeclaration => {
+                self.skip();
+                token
+            }
+            _ => try_return!(self, self.read_token_with_type(TokenType::Identifier)),
+        };
+        let kind_name = self.slice(&kind_token.span).to_owned();
+        let kind_span = kind_token.span;
+        let _ = try_return!(self, self.read_token_with_type(TokenType::ClosingParen));
+
+        let Some(kind) = RegionKind::from_name(&kind_name) else {
+            let names = RegionKind::NAMES
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>();
+
+            self.errors.push(CompileError {
+                message: format!(
+                    "Unknown region kind '{}', expected one of {}",
+                    kind_name,
+                    names.join(", ")
+                ),
+                span: kind_span,
+            });
+            return;
+        };
+
+        let range = u32::from(start)..u32::from(start) + u32::from(size);
+        if range.end > 0x10000 {
+            self.errors.push(CompileError {
+                message: format!(
+                    "Region '{}' ends at ${:X}, past the end of memory at $10000",
+                    name, range.end
+                ),
+                span: size_span,
+            });
+            return;
+        }
+
+        if let Some(existing) = self
+            .regions
+            .iter()
+            .find(|region| range.start < region.range.end && region.range.start < range.end)
+        {
+            self.errors.push(CompileError {
+                message: format!("Region '{}' overlaps with '{}'", name, existing.name),
+                span: name_span,
+            });
+            return;
+        }
+
+        self.regions.push(Region { name, range, kind });
+    }
+
+    /// Reads `@cartridge("TITLE", entry)`, which writes a Game Boy cartridge header jumping to `entry`.
+    fn read_cartridge_pragma(&mut self) {
+        let directive_span = self.latest_span.clone();
+        let _ = try_return!(self, self.read_token_with_type(TokenType::OpeningParen));
+        let title = try_return!(self, self.read_string()).to_owned();
+        let title_span = self.latest_span.clone();
+        let _ = try_return!(self, self.read_token_with_type(TokenType::Comma));
+        let entry = try_return!(self, self.read_reference());
+        let entry_span = self.latest_span.clone();
+        let _ = try_return!(self, self.read_token_with_type(TokenType::ClosingParen));
+
+        if self.target != Target::SM83 {
+            self.errors.push(CompileError {
+                message: "'@cartridge' is only available with '@target(sm83)'".to_owned(),
+                span: directive_span,
+            });
+            return;
+        }
+
+        if !title.is_ascii() || title.len() > cartridge::MAX_TITLE_LENGTH {
+            self.errors.push(CompileError {
+                message: format!(
+                    "Cartridge title '{}' has to be at most {} ASCII characters",
+                    title,
+                    cartridge::MAX_TITLE_LENGTH
+                ),
+                span: title_span,
+            });
+            return;
+        }
+
+        if let Some(existing) = self.reserve_area("cartridge header", cartridge::HEADER) {
+            self.errors.push(CompileError {
+                message: format!("Cartridge header overlaps with '{}'", existing),
+                span: directive_span,
+            });
+            return;
+        }
+
+        self.check_in_rom(
+            "The cartridge header",
+            cartridge::HEADER,
+            directive_span.clone(),
+        );
+
+        let previous_address = self.address;
+        self.set_address(cartridge::HEADER.start);
+        self.write(move |ctx| {
+            let entry = ctx.get_label(&entry, &entry_span).unwrap_or(0);
+            cartridge::header(&title, entry)
+        });
+        self.set_address(previous_address);
+        self.has_cartridge_header = true;
+    }
 }
@@ -0,0 +1,522 @@
+//! Instruction sets described as tables of every instruction form, the compiler matches operands against
+//! these to encode instructions, and the same tables provide sizes, T-states and affected flags.
+
+use super::types::{DataTarget, Value};
+
+pub use super::types::{Condition, LongRegister, Register, ShortRegister};
+
+mod mos6502;
+mod sm83;
+mod z80;
+
+pub use mos6502::{branch, MOS6502};
+pub use sm83::SM83;
+pub use z80::Z80;
+
+/// A table of instruction forms, grouped by kind.
+pub type InstructionSet = &'static [&'static [InstructionDef]];
+
+/// The CPU the code is compiled for, selected with `@target(...)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Target {
+    #[default]
+    Z80,
+    /// The Intel 8080, which runs the subset of Z80 instructions without prefixes, relative jumps or shadow registers
+    I8080,
+    /// The Sharp SM83 of the Game Boy, which has its own instruction set
+    SM83,
+    /// The MOS 6502, with only A, X and Y and addressing modes in place of register pairs
+    MOS6502,
+}
+
+impl Target {
+    pub const NAMES: &'static [(&'static str, Target)] = &[
+        ("z80", Target::Z80),
+        ("i8080", Target::I8080),
+        ("sm83", Target::SM83),
+        ("mos6502", Target::MOS6502),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Target> {
+        Target::NAMES
+            .iter()
+            .find(|(target_name, _)| *target_name == name)
+            .map(|(_, target)| *target)
+    }
+
+    /// The name of the CPU in error messages.
+    pub fn cpu_name(self) -> &'static str {
+        match self {
+            Target::Z80 => "Z80",
+            Target::I8080 => "8080",
+            Target::SM83 => "SM83",
+            Target::MOS6502 => "6502",
+        }
+    }
+
+    pub fn instruction_set(self) -> InstructionSet {
+        match self {
+            Target::Z80 | Target::I8080 => Z80,
+            Target::SM83 => SM83,
+            Target::MOS6502 => MOS6502,
+        }
+    }
+
+    /// Why a form of the instruction set can't be used on this target, for targets sharing the table of another CPU.
+    pub fn missing(self, def: &InstructionDef) -> Option<&'static str> {
+        match self {
+            Target::I8080 => def.missing_on_8080(),
+            _ => None,
+        }
+    }
+
+    /// Whether a relative jump taken if `condition` holds exists, for the jumps of structured blocks.
+    pub fn has_relative_jump(self, condition: Option<Condition>) -> bool {
+        match self {
+            Target::Z80 | Target::SM83 => {
+                condition.is_none_or(|condition| Conditions::ZeroCarry.allows(condition))
+            }
+            Target::I8080 => false,
+            Target::MOS6502 => condition.is_some(),
+        }
+    }
+
+    /// Whether the CPU has this register at all.
+    pub fn has_register(self, register: Register) -> bool {
+        if let Register::Short(ShortRegister::X | ShortRegister::Y) = register {
+            return self == Target::MOS6502;
+        }
+
+        match self {
+            Target::MOS6502 => register == Register::Short(ShortRegister::A),
+            Target::SM83 => !matches!(
+                register,
+                Register::Short(
+                    ShortRegister::I
+                        | ShortRegister::R
+                        | ShortRegister::IXH
+                        | ShortRegister::IXL
+                        | ShortRegister::IYH
+                        | ShortRegister::IYL
+                ) | Register::Long(LongRegister::IX | LongRegister::IY | LongRegister::AFShadow)
+            ),
+            // The 8080 reports Z80-only registers through the instructions using them, see `missing_on_8080`.
+            _ => true,
+        }
+    }
+}
+
+/// What an operand has to look like to match an instruction form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    /// Exactly this register, e.g the `A` in `ld A, I`
+    Reg(Register),
+    /// Exactly the memory this register points to, e.g `HL*` or the port `C*`
+    RegAddr(Register),
+    /// B, C, D, E, H, L or A, as a 3-bit field
+    R8,
+    /// B, C, D, E or A, since H and L are replaced by the index register halves behind a prefix
+    R8NoHL,
+    /// IXH, IXL, IYH or IYL, as a 3-bit field in place of H or L
+    IndexHalf,
+    /// BC, DE, HL or SP, as a 2-bit field
+    Pair,
+    /// BC, DE, HL or AF, as a 2-bit field
+    StackPair,
+    /// IX or IY, selecting the prefix
+    Index,
+    /// BC, DE, SP or the index register used by the instruction, as a 2-bit field
+    IndexPair,
+    /// `IX*` or `IY*`
+    IndexAddr,
+    /// `(IX + d)*` or `(IY + d)*`
+    Indexed,
+    Imm8,
+    Imm16,
+    /// An absolute address like `$6000*`
+    Addr16,
+    /// An 8-bit I/O port like `$10*`
+    Port,
+    /// An address in the high page `$FF00..$FFFF`, written like `$FF44*` or `$44*` and encoded as its low byte
+    HighAddr,
+    /// `(HL+)*`, the memory at HL which is incremented afterwards
+    HLIncrement,
+    /// `(HL-)*`, the memory at HL which is decremented afterwards
+    HLDecrement,
+    /// A jump target, encoded as a displacement from the end of the instruction
+    Relative,
+    /// A bit index from 0 to 7, as a 3-bit field
+    Bit,
+    /// One of the `rst` vectors $00, $08, .., $38
+    RstVector,
+    /// Interrupt mode 0, 1 or 2
+    InterruptMode,
+    /// Exactly this number, e.g the `0` in `out C*, 0`
+    Number(u16),
+    /// An address in the first 256 bytes like `$10*`, encoded as a single byte
+    ZeroPage,
+    /// A zero page address offset by X or Y, like `($10 + X)*`
+    IndexedZeroPage(ShortRegister),
+    /// An absolute address offset by X or Y, like `($1000 + Y)*`
+    IndexedAbsolute(ShortRegister),
+    /// The memory a zero page pointer at `value + X` points to, `($10 + X)**`
+    IndexedIndirect,
+    /// The memory a zero page pointer points to, offset by Y, `($10* + Y)*`
+    IndirectIndexed,
+}
+
+impl Pattern {
+    /// Whether the operand has to be known to be in the zero page while parsing, since the instruction is shorter.
+    pub fn is_zero_page(self) -> bool {
+        matches!(
+            self,
+            Pattern::ZeroPage
+                | Pattern::IndexedZeroPage(_)
+                | Pattern::IndexedIndirect
+                | Pattern::IndirectIndexed
+        )
+    }
+
+    /// Checks whether `target` has the right shape for this pattern, values are checked by `code`.
+    /// `index` is the index register used by earlier operands of the same instruction.
+    pub(crate) fn matches(self, target: &DataTarget, index: Option<LongRegister>) -> bool {
+        match (self, target) {
+            (Pattern::Reg(register), DataTarget::Register(target)) => register == *target,
+            (Pattern::RegAddr(register), DataTarget::RegisterAddress(target)) => {
+                register == *target
+            }
+            (Pattern::R8, DataTarget::Register(Register::Short(short_reg))) => {
+                short_reg.code().is_some()
+            }
+            (Pattern::R8NoHL, DataTarget::Register(Register::Short(short_reg))) => {
+                short_reg.code().is_some()
+                    && !matches!(short_reg, ShortRegister::H | ShortRegister::L)
+            }
+            (Pattern::IndexHalf, DataTarget::Register(Register::Short(short_reg))) => {
+                match short_reg.index_half() {
+                    // Both halves of an instruction have to use the same prefix.
+                    Some((index_reg, _)) => index.is_none_or(|index| index == index_reg),
+                    None => false,
+                }
+            }
+            (Pattern::Pair, DataTarget::Register(Register::Long(long_reg))) => {
+                long_reg.pair_code().is_some()
+            }
+            (Pattern::StackPair, DataTarget::Register(Register::Long(long_reg))) => {
+                long_reg.stack_code().is_some()
+            }
+            (Pattern::Index, DataTarget::Register(Register::Long(long_reg))) => {
+                long_reg.index_prefix().is_some()
+            }
+            (Pattern::IndexPair, DataTarget::Register(Register::Long(long_reg))) => {
+                matches!(
+                    long_reg,
+                    LongRegister::BC | LongRegister::DE | LongRegister::SP
+                ) || Some(*long_reg) == index
+            }
+            (Pattern::IndexAddr, DataTarget::RegisterAddress(Register::Long(long_reg))) => {
+                long_reg.index_prefix().is_some()
+            }
+            (Pattern::Indexed, DataTarget::Indexed(..)) => true,
+            (
+                Pattern::Imm8
+                | Pattern::Imm16
+                | Pattern::Relative
+                | Pattern::Bit
+                | Pattern::RstVector
+                | Pattern::InterruptMode,
+                DataTarget::Immediate(_),
+            ) => true,
+            (
+                Pattern::Addr16 | Pattern::Port | Pattern::HighAddr | Pattern::ZeroPage,
+                DataTarget::Address(_),
+            ) => true,
+            (
+                Pattern::IndexedZeroPage(index_reg) | Pattern::IndexedAbsolute(index_reg),
+                DataTarget::IndexedAddress(_, target),
+            ) => index_reg == *target,
+            (Pattern::IndexedIndirect, DataTarget::IndexedIndirect(_)) => true,
+            (Pattern::IndirectIndexed, DataTarget::IndirectIndexed(_)) => true,
+            (Pattern::HLIncrement, DataTarget::HLIncrement) => true,
+            (Pattern::HLDecrement, DataTarget::HLDecrement) => true,
+            (Pattern::Number(number), DataTarget::Immediate(Value::Literal(value))) => {
+                number == *value
+            }
+            _ => false,
+        }
+    }
+
+    /// The bits this operand contributes to the opcode, or a message if its value isn't valid.
+    pub(crate) fn code(self, target: &DataTarget) -> Result<u8, String> {
+        // Bit indices and interrupt modes are small numbers, so they're shown in decimal.
+        let number = match target {
+            DataTarget::Immediate(Value::Literal(value)) => value.to_string(),
+            _ => target.to_string(),
+        };
+
+        match (self, target) {
+            (
+                Pattern::R8 | Pattern::R8NoHL,
+                DataTarget::Register(Register::Short(short_reg)),
+            ) => Ok(short_reg.code().unwrap()),
+            (Pattern::IndexHalf, DataTarget::Register(Register::Short(short_reg))) => {
+                Ok(short_reg.index_half().unwrap().1)
+            }
+            (Pattern::Pair, DataTarget::Register(Register::Long(long_reg))) => {
+                Ok(long_reg.pair_code().unwrap())
+            }
+            (Pattern::StackPair, DataTarget::Register(Register::Long(long_reg))) => {
+                Ok(long_reg.stack_code().unwrap())
+            }
+            (Pattern::IndexPair, DataTarget::Register(Register::Long(long_reg))) => {
+                // The index register itself takes the place of HL.
+                Ok(long_reg.pair_code().unwrap_or(2))
+            }
+            (Pattern::Bit, DataTarget::Immediate(Value::Literal(bit))) if *bit <= 7 => {
+                Ok(*bit as u8)
+            }
+            (Pattern::Bit, _) => Err(format!(
+                "Expected a bit index from 0 to 7, found '{}'",
+                number
+            )),
+            (Pattern::RstVector, DataTarget::Immediate(Value::Literal(vector)))
+                if *vector <= 0x38 && vector % 8 == 0 =>
+            {
+                Ok(*vector as u8)
+            }
+            (Pattern::RstVector, _) => Err(format!(
+                "Invalid restart vector '{}', expected one of $00, $08, $10, $18, $20, $28, $30 or $38",
+                target
+            )),
+            (Pattern::InterruptMode, DataTarget::Immediate(Value::Literal(mode))) if *mode <= 2 => {
+                // Mode 0 is encoded as 0, but modes 1 and 2 as 2 and 3.
+                Ok(if *mode == 0 { 0 } else { *mode as u8 + 1 })
+            }
+            (Pattern::InterruptMode, _) => Err(format!(
+                "Invalid interrupt mode '{}', expected 0, 1 or 2",
+                number
+            )),
+            _ => Ok(0),
+        }
+    }
+
+    /// Names of the operands this pattern accepts, for error messages.
+    pub fn describe(self, index: Option<LongRegister>) -> Vec<String> {
+        let names: &[&str] = match self {
+            Pattern::Reg(register) => return vec![register.to_string()],
+            Pattern::RegAddr(register) => return vec![format!("{}*", register)],
+            Pattern::IndexPair => {
+                let index = index.map(|index| format!("{:?}", index));
+                return ["BC", "DE"]
+                    .into_iter()
+                    .map(str::to_owned)
+                    .chain(index)
+                    .chain(["SP".to_owned()])
+                    .collect();
+            }
+            Pattern::IndexHalf => match index {
+                Some(LongRegister::IY) => &["IYH", "IYL"],
+                Some(_) => &["IXH", "IXL"],
+                None => &["IXH", "IXL", "IYH", "IYL"],
+            },
+            Pattern::Number(number) => return vec![number.to_string()],
+            Pattern::IndexedZeroPage(index_reg) => {
+                return vec![format!("a zero page address indexed by {:?}", index_reg)]
+            }
+            Pattern::IndexedAbsolute(index_reg) => {
+                return vec![format!("an address indexed by {:?}", index_reg)]
+            }
+            Pattern::R8 => &["an 8-bit register"],
+            Pattern::R8NoHL => &["B", "C", "D", "E", "A"],
+            Pattern::Pair => &["BC", "DE", "HL", "SP"],
+            Pattern::StackPair => &["BC", "DE", "HL", "AF"],
+            Pattern::Index => &["IX", "IY"],
+            Pattern::IndexAddr => &["IX*", "IY*"],
+            Pattern::Indexed => &["an indexed address"],
+            Pattern::Imm8 => &["an 8-bit number"],
+            Pattern::Imm16 => &["a 16-bit number"],
+            Pattern::Addr16 => &["an address"],
+            Pattern::Port => &["a port"],
+            Pattern::HighAddr => &["a high page address"],
+            Pattern::HLIncrement => &["(HL+)*"],
+            Pattern::HLDecrement => &["(HL-)*"],
+            Pattern::Relative => &["a label"],
+            Pattern::Bit => &["a bit index"],
+            Pattern::RstVector => &["a restart vector"],
+            Pattern::InterruptMode => &["an interrupt mode"],
+            Pattern::ZeroPage => &["a zero page address"],
+            Pattern::IndexedIndirect => &["($nn + X)**"],
+            Pattern::IndirectIndexed => &["($nn* + Y)*"],
+        };
+
+        names.iter().map(|name| name.to_string()).collect()
+    }
+}
+
+/// Where the bits OR'd into an opcode come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// The code of the operand at this index, shifted left
+    Operand(usize, u8),
+    /// The code of the condition, shifted left by 3
+    Condition,
+}
+
+/// One part of an encoded instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Enc {
+    Byte(u8),
+    /// An opcode with fields OR'd in
+    Op(u8, &'static [Field]),
+    /// DD or FD, depending on the index register used by the instruction
+    IndexPrefix,
+    /// The displacement of the indexed operand at this index
+    Disp(usize),
+    /// The value of the operand at this index
+    Imm8(usize),
+    /// The value of the operand at this index, little endian
+    Imm16(usize),
+    /// The distance from the end of the instruction to the operand at this index
+    Rel(usize),
+}
+
+impl Enc {
+    pub const fn size(self) -> u16 {
+        match self {
+            Enc::Imm16(_) => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Which conditions can be appended to an instruction with `if`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conditions {
+    None,
+    All,
+    /// Only Zero and Carry, used by relative jumps
+    ZeroCarry,
+}
+
+impl Conditions {
+    pub fn allows(self, condition: Condition) -> bool {
+        match self {
+            Conditions::None => false,
+            Conditions::All => true,
+            Conditions::ZeroCarry => condition.code() < 4,
+        }
+    }
+}
+
+/// A single form of an instruction, like `ld r, n`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstructionDef {
+    pub mnemonic: &'static str,
+    pub operands: &'static [Pattern],
+    pub conditions: Conditions,
+    pub encoding: &'static [Enc],
+    /// T-states, or cycles on the 6502.
+    /// The second one applies when the condition isn't met, a branch isn't taken or a repeating instruction finishes.
+    pub t_states: (u8, u8),
+    /// How the flags are affected, in the order S, Z, H, P/V, N, C, or Z, N, H, C on the SM83 and N, V, D, I, Z, C on the 6502.
+    /// `-` is unaffected, `*` affected, `0` reset, `1` set, `P` parity, `V` overflow and `?` undefined.
+    pub flags: &'static str,
+    /// Works on real hardware but isn't part of the official documentation, only allowed with `@undocumented`
+    pub undocumented: bool,
+}
+
+impl InstructionDef {
+    pub const fn new(
+        mnemonic: &'static str,
+        operands: &'static [Pattern],
+        encoding: &'static [Enc],
+        t_states: u8,
+        flags: &'static str,
+    ) -> Self {
+        Self {
+            mnemonic,
+            operands,
+            conditions: Conditions::None,
+            encoding,
+            t_states: (t_states, t_states),
+            flags,
+            undocumented: false,
+        }
+    }
+
+    pub const fn conditional(mut self, conditions: Conditions) -> Self {
+        self.conditions = conditions;
+        self
+    }
+
+    /// Sets the T-states for when the condition isn't met, a branch isn't taken or a repeating instruction finishes.
+    pub const fn otherwise(mut self, t_states: u8) -> Self {
+        self.t_states.1 = t_states;
+        self
+    }
+
+    pub const fn undocumented(mut self) -> Self {
+        self.undocumented = true;
+        self
+    }
+
+    pub fn size(&self) -> u16 {
+        self.encoding.iter().map(|enc| enc.size()).sum()
+    }
+
+    /// Whether execution never continues past this instruction, so it can end a block.
+    pub fn ends_block(&self) -> bool {
+        const TERMINATORS: &[&str] = &[
+            "jp", "jr", "jmp", "ret", "reti", "retn", "rts", "rti", "halt", "hlt",
+        ];
+
+        self.conditions == Conditions::None && TERMINATORS.contains(&self.mnemonic)
+    }
+
+    /// Why this form doesn't exist on the 8080, or `None` if it does.
+    pub fn missing_on_8080(&self) -> Option<&'static str> {
+        let shadow = self.mnemonic == "exx"
+            || self
+                .operands
+                .contains(&Pattern::Reg(Register::Long(LongRegister::AFShadow)));
+
+        match self.encoding.first() {
+            Some(Enc::IndexPrefix | Enc::Byte(0xDD | 0xFD)) => Some("it has no IX or IY registers"),
+            Some(Enc::Byte(0xCB)) => Some("it has no CB-prefixed instructions"),
+            Some(Enc::Byte(0xED)) => Some("it has no ED-prefixed instructions"),
+            _ if self.encoding.iter().any(|enc| matches!(enc, Enc::Rel(_))) => {
+                Some("it has no relative jumps")
+            }
+            _ if shadow => Some("it has no shadow registers"),
+            _ => None,
+        }
+    }
+
+    /// The flags as documented for `target`, the 8080 has no overflow flag so P/V always holds the parity.
+    pub fn flags_for(&self, target: Target) -> String {
+        match target {
+            Target::Z80 | Target::SM83 | Target::MOS6502 => self.flags.to_owned(),
+            Target::I8080 => self.flags.replace('V', "P"),
+        }
+    }
+}
+
+pub(crate) fn is_mnemonic(set: InstructionSet, mnemonic: &str) -> bool {
+    forms(set, mnemonic).next().is_some()
+}
+
+/// Whether `mnemonic` exists on any target, to tell a mnemonic of another target from a typo.
+pub(crate) fn is_any_mnemonic(mnemonic: &str) -> bool {
+    Target::NAMES
+        .iter()
+        .any(|(_, target)| is_mnemonic(target.instruction_set(), mnemonic))
+}
+
+pub(crate) fn forms<'a>(
+    set: InstructionSet,
+    mnemonic: &'a str,
+) -> impl Iterator<Item = &'static InstructionDef> + 'a {
+    set.iter()
+        .flat_map(|group| group.iter())
+        .filter(move |def| def.mnemonic == mnemonic)
+}
@@ -0,0 +1,208 @@
+use super::{
+    Condition, Enc,
+    Enc::{Byte, Rel},
+    InstructionDef as Def, InstructionSet, Pattern,
+    Pattern::*,
+    Register, ShortRegister,
+};
+
+const A: Pattern = Reg(Register::Short(ShortRegister::A));
+const ZERO_PAGE_X: Pattern = IndexedZeroPage(ShortRegister::X);
+const ZERO_PAGE_Y: Pattern = IndexedZeroPage(ShortRegister::Y);
+const ABSOLUTE_X: Pattern = IndexedAbsolute(ShortRegister::X);
+const ABSOLUTE_Y: Pattern = IndexedAbsolute(ShortRegister::Y);
+
+/// The instructions with every addressing mode, the mode is added to the base opcode.
+#[rustfmt::skip]
+macro_rules! group_one {
+    ($mnemonic:literal, $base:literal, $flags:literal) => {
+        [
+            Def::new($mnemonic, &[Imm8], &[Byte($base | 0x09), Enc::Imm8(0)], 2, $flags),
+            Def::new($mnemonic, &[ZeroPage], &[Byte($base | 0x05), Enc::Imm8(0)], 3, $flags),
+            Def::new($mnemonic, &[ZERO_PAGE_X], &[Byte($base | 0x15), Enc::Imm8(0)], 4, $flags),
+            Def::new($mnemonic, &[Addr16], &[Byte($base | 0x0D), Enc::Imm16(0)], 4, $flags),
+            Def::new($mnemonic, &[ABSOLUTE_X], &[Byte($base | 0x1D), Enc::Imm16(0)], 4, $flags),
+            Def::new($mnemonic, &[ABSOLUTE_Y], &[Byte($base | 0x19), Enc::Imm16(0)], 4, $flags),
+            Def::new($mnemonic, &[IndexedIndirect], &[Byte($base | 0x01), Enc::Imm8(0)], 6, $flags),
+            Def::new($mnemonic, &[IndirectIndexed], &[Byte($base | 0x11), Enc::Imm8(0)], 5, $flags),
+        ]
+    };
+}
+
+/// Shifts and rotates work on the accumulator, written as `A` or left out, or on memory.
+#[rustfmt::skip]
+macro_rules! shift {
+    ($mnemonic:literal, $base:literal, $flags:literal) => {
+        [
+            Def::new($mnemonic, &[], &[Byte($base | 0x0A)], 2, $flags),
+            Def::new($mnemonic, &[A], &[Byte($base | 0x0A)], 2, $flags),
+            Def::new($mnemonic, &[ZeroPage], &[Byte($base | 0x06), Enc::Imm8(0)], 5, $flags),
+            Def::new($mnemonic, &[ZERO_PAGE_X], &[Byte($base | 0x16), Enc::Imm8(0)], 6, $flags),
+            Def::new($mnemonic, &[Addr16], &[Byte($base | 0x0E), Enc::Imm16(0)], 6, $flags),
+            Def::new($mnemonic, &[ABSOLUTE_X], &[Byte($base | 0x1E), Enc::Imm16(0)], 7, $flags),
+        ]
+    };
+}
+
+/// Compares against X or Y.
+#[rustfmt::skip]
+macro_rules! compare_index {
+    ($mnemonic:literal, $base:literal) => {
+        [
+            Def::new($mnemonic, &[Imm8], &[Byte($base), Enc::Imm8(0)], 2, "*---**"),
+            Def::new($mnemonic, &[ZeroPage], &[Byte($base | 0x04), Enc::Imm8(0)], 3, "*---**"),
+            Def::new($mnemonic, &[Addr16], &[Byte($base | 0x0C), Enc::Imm16(0)], 4, "*---**"),
+        ]
+    };
+}
+
+const GROUP_ONE: &[&[Def]] = &[
+    &group_one!("ora", 0x00, "*---*-"),
+    &group_one!("and", 0x20, "*---*-"),
+    &group_one!("eor", 0x40, "*---*-"),
+    &group_one!("adc", 0x60, "**--**"),
+    &group_one!("lda", 0xA0, "*---*-"),
+    &group_one!("cmp", 0xC0, "*---**"),
+    &group_one!("sbc", 0xE0, "**--**"),
+];
+
+/// Stores take longer with indexing, since they can't skip the page crossing fixup.
+#[rustfmt::skip]
+const STORE: &[Def] = &[
+    Def::new("sta", &[ZeroPage], &[Byte(0x85), Enc::Imm8(0)], 3, "------"),
+    Def::new("sta", &[ZERO_PAGE_X], &[Byte(0x95), Enc::Imm8(0)], 4, "------"),
+    Def::new("sta", &[Addr16], &[Byte(0x8D), Enc::Imm16(0)], 4, "------"),
+    Def::new("sta", &[ABSOLUTE_X], &[Byte(0x9D), Enc::Imm16(0)], 5, "------"),
+    Def::new("sta", &[ABSOLUTE_Y], &[Byte(0x99), Enc::Imm16(0)], 5, "------"),
+    Def::new("sta", &[IndexedIndirect], &[Byte(0x81), Enc::Imm8(0)], 6, "------"),
+    Def::new("sta", &[IndirectIndexed], &[Byte(0x91), Enc::Imm8(0)], 6, "------"),
+    Def::new("stx", &[ZeroPage], &[Byte(0x86), Enc::Imm8(0)], 3, "------"),
+    Def::new("stx", &[ZERO_PAGE_Y], &[Byte(0x96), Enc::Imm8(0)], 4, "------"),
+    Def::new("stx", &[Addr16], &[Byte(0x8E), Enc::Imm16(0)], 4, "------"),
+    Def::new("sty", &[ZeroPage], &[Byte(0x84), Enc::Imm8(0)], 3, "------"),
+    Def::new("sty", &[ZERO_PAGE_X], &[Byte(0x94), Enc::Imm8(0)], 4, "------"),
+    Def::new("sty", &[Addr16], &[Byte(0x8C), Enc::Imm16(0)], 4, "------"),
+];
+
+#[rustfmt::skip]
+const LOAD_INDEX: &[Def] = &[
+    Def::new("ldx", &[Imm8], &[Byte(0xA2), Enc::Imm8(0)], 2, "*---*-"),
+    Def::new("ldx", &[ZeroPage], &[Byte(0xA6), Enc::Imm8(0)], 3, "*---*-"),
+    Def::new("ldx", &[ZERO_PAGE_Y], &[Byte(0xB6), Enc::Imm8(0)], 4, "*---*-"),
+    Def::new("ldx", &[Addr16], &[Byte(0xAE), Enc::Imm16(0)], 4, "*---*-"),
+    Def::new("ldx", &[ABSOLUTE_Y], &[Byte(0xBE), Enc::Imm16(0)], 4, "*---*-"),
+    Def::new("ldy", &[Imm8], &[Byte(0xA0), Enc::Imm8(0)], 2, "*---*-"),
+    Def::new("ldy", &[ZeroPage], &[Byte(0xA4), Enc::Imm8(0)], 3, "*---*-"),
+    Def::new("ldy", &[ZERO_PAGE_X], &[Byte(0xB4), Enc::Imm8(0)], 4, "*---*-"),
+    Def::new("ldy", &[Addr16], &[Byte(0xAC), Enc::Imm16(0)], 4, "*---*-"),
+    Def::new("ldy", &[ABSOLUTE_X], &[Byte(0xBC), Enc::Imm16(0)], 4, "*---*-"),
+];
+
+const COMPARE_INDEX: &[&[Def]] = &[&compare_index!("cpx", 0xE0), &compare_index!("cpy", 0xC0)];
+
+const SHIFT: &[&[Def]] = &[
+    &shift!("asl", 0x00, "*---**"),
+    &shift!("rol", 0x20, "*---**"),
+    &shift!("lsr", 0x40, "0---**"),
+    &shift!("ror", 0x60, "*---**"),
+];
+
+#[rustfmt::skip]
+const ARITHMETIC: &[Def] = &[
+    Def::new("inc", &[ZeroPage], &[Byte(0xE6), Enc::Imm8(0)], 5, "*---*-"),
+    Def::new("inc", &[ZERO_PAGE_X], &[Byte(0xF6), Enc::Imm8(0)], 6, "*---*-"),
+    Def::new("inc", &[Addr16], &[Byte(0xEE), Enc::Imm16(0)], 6, "*---*-"),
+    Def::new("inc", &[ABSOLUTE_X], &[Byte(0xFE), Enc::Imm16(0)], 7, "*---*-"),
+    Def::new("dec", &[ZeroPage], &[Byte(0xC6), Enc::Imm8(0)], 5, "*---*-"),
+    Def::new("dec", &[ZERO_PAGE_X], &[Byte(0xD6), Enc::Imm8(0)], 6, "*---*-"),
+    Def::new("dec", &[Addr16], &[Byte(0xCE), Enc::Imm16(0)], 6, "*---*-"),
+    Def::new("dec", &[ABSOLUTE_X], &[Byte(0xDE), Enc::Imm16(0)], 7, "*---*-"),
+    Def::new("inx", &[], &[Byte(0xE8)], 2, "*---*-"),
+    Def::new("iny", &[], &[Byte(0xC8)], 2, "*---*-"),
+    Def::new("dex", &[], &[Byte(0xCA)], 2, "*---*-"),
+    Def::new("dey", &[], &[Byte(0x88)], 2, "*---*-"),
+    Def::new("bit", &[ZeroPage], &[Byte(0x24), Enc::Imm8(0)], 3, "**--*-"),
+    Def::new("bit", &[Addr16], &[Byte(0x2C), Enc::Imm16(0)], 4, "**--*-"),
+];
+
+#[rustfmt::skip]
+const TRANSFER: &[Def] = &[
+    Def::new("tax", &[], &[Byte(0xAA)], 2, "*---*-"),
+    Def::new("tay", &[], &[Byte(0xA8)], 2, "*---*-"),
+    Def::new("txa", &[], &[Byte(0x8A)], 2, "*---*-"),
+    Def::new("tya", &[], &[Byte(0x98)], 2, "*---*-"),
+    Def::new("tsx", &[], &[Byte(0xBA)], 2, "*---*-"),
+    Def::new("txs", &[], &[Byte(0x9A)], 2, "------"),
+    Def::new("pha", &[], &[Byte(0x48)], 3, "------"),
+    Def::new("php", &[], &[Byte(0x08)], 3, "------"),
+    Def::new("pla", &[], &[Byte(0x68)], 4, "*---*-"),
+    Def::new("plp", &[], &[Byte(0x28)], 4, "******"),
+];
+
+#[rustfmt::skip]
+const CONTROL: &[Def] = &[
+    Def::new("nop", &[], &[Byte(0xEA)], 2, "------"),
+    Def::new("brk", &[], &[Byte(0x00)], 7, "---1--"),
+    Def::new("clc", &[], &[Byte(0x18)], 2, "-----0"),
+    Def::new("sec", &[], &[Byte(0x38)], 2, "-----1"),
+    Def::new("cli", &[], &[Byte(0x58)], 2, "---0--"),
+    Def::new("sei", &[], &[Byte(0x78)], 2, "---1--"),
+    Def::new("clv", &[], &[Byte(0xB8)], 2, "-0----"),
+    Def::new("cld", &[], &[Byte(0xD8)], 2, "--0---"),
+    Def::new("sed", &[], &[Byte(0xF8)], 2, "--1---"),
+];
+
+/// `jmp $FFFC*` jumps to the address stored at $FFFC. Branches test a single flag each, so they can't use `if`.
+#[rustfmt::skip]
+const JUMP: &[Def] = &[
+    Def::new("jmp", &[Imm16], &[Byte(0x4C), Enc::Imm16(0)], 3, "------"),
+    Def::new("jmp", &[Addr16], &[Byte(0x6C), Enc::Imm16(0)], 5, "------"),
+    Def::new("jsr", &[Imm16], &[Byte(0x20), Enc::Imm16(0)], 6, "------"),
+    Def::new("rts", &[], &[Byte(0x60)], 6, "------"),
+    Def::new("rti", &[], &[Byte(0x40)], 6, "******"),
+    Def::new("bpl", &[Relative], &[Byte(0x10), Rel(0)], 3, "------").otherwise(2),
+    Def::new("bmi", &[Relative], &[Byte(0x30), Rel(0)], 3, "------").otherwise(2),
+    Def::new("bvc", &[Relative], &[Byte(0x50), Rel(0)], 3, "------").otherwise(2),
+    Def::new("bvs", &[Relative], &[Byte(0x70), Rel(0)], 3, "------").otherwise(2),
+    Def::new("bcc", &[Relative], &[Byte(0x90), Rel(0)], 3, "------").otherwise(2),
+    Def::new("bcs", &[Relative], &[Byte(0xB0), Rel(0)], 3, "------").otherwise(2),
+    Def::new("bne", &[Relative], &[Byte(0xD0), Rel(0)], 3, "------").otherwise(2),
+    Def::new("beq", &[Relative], &[Byte(0xF0), Rel(0)], 3, "------").otherwise(2),
+];
+
+/// The branch taken if `condition` holds, the 6502 keeps overflow where the Z80 keeps parity.
+pub fn branch(condition: Condition) -> &'static str {
+    match condition {
+        Condition::NotZero => "bne",
+        Condition::Zero => "beq",
+        Condition::NoCarry => "bcc",
+        Condition::Carry => "bcs",
+        Condition::ParityOdd => "bvc",
+        Condition::ParityEven => "bvs",
+        Condition::Positive => "bpl",
+        Condition::Negative => "bmi",
+    }
+}
+
+/// Every documented 6502 instruction.
+pub static MOS6502: InstructionSet = &[
+    GROUP_ONE[0],
+    GROUP_ONE[1],
+    GROUP_ONE[2],
+    GROUP_ONE[3],
+    GROUP_ONE[4],
+    GROUP_ONE[5],
+    GROUP_ONE[6],
+    STORE,
+    LOAD_INDEX,
+    COMPARE_INDEX[0],
+    COMPARE_INDEX[1],
+    SHIFT[0],
+    SHIFT[1],
+    SHIFT[2],
+    SHIFT[3],
+    ARITHMETIC,
+    TRANSFER,
+    CONTROL,
+    JUMP,
+];
@@ -0,0 +1,165 @@
+use super::{
+    Conditions, Enc,
+    Enc::{Byte, Op, Rel},
+    Field::{self, Condition},
+    InstructionDef as Def, InstructionSet, LongRegister, Pattern,
+    Pattern::*,
+    Register, ShortRegister,
+};
+
+const A: Pattern = Reg(Register::Short(ShortRegister::A));
+const HL: Pattern = Reg(Register::Long(LongRegister::HL));
+const SP: Pattern = Reg(Register::Long(LongRegister::SP));
+const HL_ADDR: Pattern = RegAddr(Register::Long(LongRegister::HL));
+const BC_ADDR: Pattern = RegAddr(Register::Long(LongRegister::BC));
+const DE_ADDR: Pattern = RegAddr(Register::Long(LongRegister::DE));
+const C_ADDR: Pattern = RegAddr(Register::Short(ShortRegister::C));
+
+const fn op(index: usize, shift: u8) -> Field {
+    Field::Operand(index, shift)
+}
+
+/// The 8-bit arithmetic and logic instructions, encoded like on the Z80.
+#[rustfmt::skip]
+macro_rules! alu {
+    ($mnemonic:literal, $op:literal, $flags:literal) => {
+        [
+            Def::new($mnemonic, &[R8], &[Op(0x80 | $op << 3, &[op(0, 0)])], 4, $flags),
+            Def::new($mnemonic, &[Imm8], &[Byte(0xC6 | $op << 3), Enc::Imm8(0)], 8, $flags),
+            Def::new($mnemonic, &[HL_ADDR], &[Byte(0x86 | $op << 3)], 8, $flags),
+            Def::new($mnemonic, &[A, R8], &[Op(0x80 | $op << 3, &[op(1, 0)])], 4, $flags),
+            Def::new($mnemonic, &[A, Imm8], &[Byte(0xC6 | $op << 3), Enc::Imm8(1)], 8, $flags),
+            Def::new($mnemonic, &[A, HL_ADDR], &[Byte(0x86 | $op << 3)], 8, $flags),
+        ]
+    };
+}
+
+/// The CB-prefixed rotates and shifts, `swap` takes the place of the Z80's undocumented `sll`.
+#[rustfmt::skip]
+macro_rules! rotate {
+    ($mnemonic:literal, $op:literal, $flags:literal) => {
+        [
+            Def::new($mnemonic, &[R8], &[Byte(0xCB), Op($op << 3, &[op(0, 0)])], 8, $flags),
+            Def::new($mnemonic, &[HL_ADDR], &[Byte(0xCB), Byte($op << 3 | 6)], 16, $flags),
+        ]
+    };
+}
+
+#[rustfmt::skip]
+macro_rules! bit_op {
+    ($mnemonic:literal, $base:literal, $t_states_memory:literal, $flags:literal) => {
+        [
+            Def::new($mnemonic, &[Bit, R8], &[Byte(0xCB), Op($base, &[op(0, 3), op(1, 0)])], 8, $flags),
+            Def::new($mnemonic, &[Bit, HL_ADDR], &[Byte(0xCB), Op($base | 6, &[op(0, 3)])], $t_states_memory, $flags),
+        ]
+    };
+}
+
+#[rustfmt::skip]
+const LOAD: &[Def] = &[
+    Def::new("ld", &[R8, R8], &[Op(0x40, &[op(0, 3), op(1, 0)])], 4, "----"),
+    Def::new("ld", &[R8, Imm8], &[Op(0x06, &[op(0, 3)]), Enc::Imm8(1)], 8, "----"),
+    Def::new("ld", &[R8, HL_ADDR], &[Op(0x46, &[op(0, 3)])], 8, "----"),
+    Def::new("ld", &[HL_ADDR, R8], &[Op(0x70, &[op(1, 0)])], 8, "----"),
+    Def::new("ld", &[HL_ADDR, Imm8], &[Byte(0x36), Enc::Imm8(1)], 12, "----"),
+    Def::new("ld", &[A, BC_ADDR], &[Byte(0x0A)], 8, "----"),
+    Def::new("ld", &[A, DE_ADDR], &[Byte(0x1A)], 8, "----"),
+    Def::new("ld", &[BC_ADDR, A], &[Byte(0x02)], 8, "----"),
+    Def::new("ld", &[DE_ADDR, A], &[Byte(0x12)], 8, "----"),
+    Def::new("ld", &[A, Addr16], &[Byte(0xFA), Enc::Imm16(1)], 16, "----"),
+    Def::new("ld", &[Addr16, A], &[Byte(0xEA), Enc::Imm16(0)], 16, "----"),
+    Def::new("ld", &[HLIncrement, A], &[Byte(0x22)], 8, "----"),
+    Def::new("ld", &[HLDecrement, A], &[Byte(0x32)], 8, "----"),
+    Def::new("ld", &[A, HLIncrement], &[Byte(0x2A)], 8, "----"),
+    Def::new("ld", &[A, HLDecrement], &[Byte(0x3A)], 8, "----"),
+    Def::new("ldh", &[A, HighAddr], &[Byte(0xF0), Enc::Imm8(1)], 12, "----"),
+    Def::new("ldh", &[HighAddr, A], &[Byte(0xE0), Enc::Imm8(0)], 12, "----"),
+    Def::new("ldh", &[A, C_ADDR], &[Byte(0xF2)], 8, "----"),
+    Def::new("ldh", &[C_ADDR, A], &[Byte(0xE2)], 8, "----"),
+    Def::new("ld", &[Pair, Imm16], &[Op(0x01, &[op(0, 4)]), Enc::Imm16(1)], 12, "----"),
+    Def::new("ld", &[Addr16, SP], &[Byte(0x08), Enc::Imm16(0)], 20, "----"),
+    Def::new("ld", &[SP, HL], &[Byte(0xF9)], 8, "----"),
+    Def::new("push", &[StackPair], &[Op(0xC5, &[op(0, 4)])], 16, "----"),
+    Def::new("pop", &[StackPair], &[Op(0xC1, &[op(0, 4)])], 12, "----"),
+];
+
+const ALU_8: &[&[Def]] = &[
+    &alu!("add", 0, "*0**"),
+    &alu!("adc", 1, "*0**"),
+    &alu!("sub", 2, "*1**"),
+    &alu!("sbc", 3, "*1**"),
+    &alu!("and", 4, "*010"),
+    &alu!("xor", 5, "*000"),
+    &alu!("or", 6, "*000"),
+    &alu!("cp", 7, "*1**"),
+];
+
+#[rustfmt::skip]
+const ARITHMETIC: &[Def] = &[
+    Def::new("inc", &[R8], &[Op(0x04, &[op(0, 3)])], 4, "*0*-"),
+    Def::new("inc", &[HL_ADDR], &[Byte(0x34)], 12, "*0*-"),
+    Def::new("dec", &[R8], &[Op(0x05, &[op(0, 3)])], 4, "*1*-"),
+    Def::new("dec", &[HL_ADDR], &[Byte(0x35)], 12, "*1*-"),
+    Def::new("inc", &[Pair], &[Op(0x03, &[op(0, 4)])], 8, "----"),
+    Def::new("dec", &[Pair], &[Op(0x0B, &[op(0, 4)])], 8, "----"),
+    Def::new("add", &[HL, Pair], &[Op(0x09, &[op(1, 4)])], 8, "-0**"),
+    Def::new("add", &[SP, Imm8], &[Byte(0xE8), Enc::Imm8(1)], 16, "00**"),
+];
+
+#[rustfmt::skip]
+const CONTROL: &[Def] = &[
+    Def::new("nop", &[], &[Byte(0x00)], 4, "----"),
+    Def::new("halt", &[], &[Byte(0x76)], 4, "----"),
+    Def::new("hlt", &[], &[Byte(0x76)], 4, "----"),
+    Def::new("stop", &[], &[Byte(0x10), Byte(0x00)], 4, "----"),
+    Def::new("di", &[], &[Byte(0xF3)], 4, "----"),
+    Def::new("ei", &[], &[Byte(0xFB)], 4, "----"),
+    Def::new("daa", &[], &[Byte(0x27)], 4, "*-0*"),
+    Def::new("cpl", &[], &[Byte(0x2F)], 4, "-11-"),
+    Def::new("ccf", &[], &[Byte(0x3F)], 4, "-00*"),
+    Def::new("scf", &[], &[Byte(0x37)], 4, "-001"),
+];
+
+#[rustfmt::skip]
+const ROTATE: &[&[Def]] = &[
+    &[
+        Def::new("rlca", &[], &[Byte(0x07)], 4, "000*"),
+        Def::new("rrca", &[], &[Byte(0x0F)], 4, "000*"),
+        Def::new("rla", &[], &[Byte(0x17)], 4, "000*"),
+        Def::new("rra", &[], &[Byte(0x1F)], 4, "000*"),
+    ],
+    &rotate!("rlc", 0, "*00*"),
+    &rotate!("rrc", 1, "*00*"),
+    &rotate!("rl", 2, "*00*"),
+    &rotate!("rr", 3, "*00*"),
+    &rotate!("sla", 4, "*00*"),
+    &rotate!("sra", 5, "*00*"),
+    &rotate!("swap", 6, "*000"),
+    &rotate!("srl", 7, "*00*"),
+    &bit_op!("bit", 0x40, 12, "*01-"),
+    &bit_op!("res", 0x80, 16, "----"),
+    &bit_op!("set", 0xC0, 16, "----"),
+];
+
+/// Conditional jumps, calls and returns only exist for the Zero and Carry flags.
+#[rustfmt::skip]
+const JUMP: &[Def] = &[
+    Def::new("jp", &[Imm16], &[Byte(0xC3), Enc::Imm16(0)], 16, "----"),
+    Def::new("jp", &[Imm16], &[Op(0xC2, &[Condition]), Enc::Imm16(0)], 16, "----").conditional(Conditions::ZeroCarry).otherwise(12),
+    Def::new("jp", &[HL_ADDR], &[Byte(0xE9)], 4, "----"),
+    Def::new("jr", &[Relative], &[Byte(0x18), Rel(0)], 12, "----"),
+    Def::new("jr", &[Relative], &[Op(0x20, &[Condition]), Rel(0)], 12, "----").conditional(Conditions::ZeroCarry).otherwise(8),
+    Def::new("call", &[Imm16], &[Byte(0xCD), Enc::Imm16(0)], 24, "----"),
+    Def::new("call", &[Imm16], &[Op(0xC4, &[Condition]), Enc::Imm16(0)], 24, "----").conditional(Conditions::ZeroCarry).otherwise(12),
+    Def::new("ret", &[], &[Byte(0xC9)], 16, "----"),
+    Def::new("ret", &[], &[Op(0xC0, &[Condition])], 20, "----").conditional(Conditions::ZeroCarry).otherwise(8),
+    Def::new("reti", &[], &[Byte(0xD9)], 16, "----"),
+    Def::new("rst", &[RstVector], &[Op(0xC7, &[op(0, 0)])], 16, "----"),
+];
+
+/// Every SM83 instruction.
+pub static SM83: InstructionSet = &[
+    LOAD, ALU_8[0], ALU_8[1], ALU_8[2], ALU_8[3], ALU_8[4], ALU_8[5], ALU_8[6], ALU_8[7],
+    ARITHMETIC, CONTROL, ROTATE[0], ROTATE[1], ROTATE[2], ROTATE[3], ROTATE[4], ROTATE[5],
+    ROTATE[6], ROTATE[7], ROTATE[8], ROTATE[9], ROTATE[10], ROTATE[11], JUMP,
+];
@@ -0,0 +1,342 @@
+use super::{
+    Conditions, Enc,
+    Enc::{Byte, Disp, IndexPrefix, Op, Rel},
+    Field::{self, Condition},
+    InstructionDef as Def, InstructionSet, LongRegister, Pattern,
+    Pattern::*,
+    Register, ShortRegister,
+};
+
+const A: Pattern = Reg(Register::Short(ShortRegister::A));
+const I: Pattern = Reg(Register::Short(ShortRegister::I));
+const R: Pattern = Reg(Register::Short(ShortRegister::R));
+const HL: Pattern = Reg(Register::Long(LongRegister::HL));
+const DE: Pattern = Reg(Register::Long(LongRegister::DE));
+const SP: Pattern = Reg(Register::Long(LongRegister::SP));
+const AF: Pattern = Reg(Register::Long(LongRegister::AF));
+const AF_SHADOW: Pattern = Reg(Register::Long(LongRegister::AFShadow));
+const HL_ADDR: Pattern = RegAddr(Register::Long(LongRegister::HL));
+const BC_ADDR: Pattern = RegAddr(Register::Long(LongRegister::BC));
+const DE_ADDR: Pattern = RegAddr(Register::Long(LongRegister::DE));
+const SP_ADDR: Pattern = RegAddr(Register::Long(LongRegister::SP));
+const C_PORT: Pattern = RegAddr(Register::Short(ShortRegister::C));
+
+const fn op(index: usize, shift: u8) -> Field {
+    Field::Operand(index, shift)
+}
+
+/// Marks every form of a group as undocumented.
+const fn undocumented<const N: usize>(mut defs: [Def; N]) -> [Def; N] {
+    let mut i = 0;
+    while i < N {
+        defs[i].undocumented = true;
+        i += 1;
+    }
+    defs
+}
+
+/// The 8-bit arithmetic and logic instructions, `op` selects the operation.
+/// The accumulator can be left out, `sub B` and `sub A, B` are equivalent.
+#[rustfmt::skip]
+macro_rules! alu {
+    ($mnemonic:literal, $op:literal, $flags:literal) => {
+        [
+            Def::new($mnemonic, &[R8], &[Op(0x80 | $op << 3, &[op(0, 0)])], 4, $flags),
+            Def::new($mnemonic, &[Imm8], &[Byte(0xC6 | $op << 3), Enc::Imm8(0)], 7, $flags),
+            Def::new($mnemonic, &[HL_ADDR], &[Byte(0x86 | $op << 3)], 7, $flags),
+            Def::new($mnemonic, &[Indexed], &[IndexPrefix, Byte(0x86 | $op << 3), Disp(0)], 19, $flags),
+            Def::new($mnemonic, &[A, R8], &[Op(0x80 | $op << 3, &[op(1, 0)])], 4, $flags),
+            Def::new($mnemonic, &[A, Imm8], &[Byte(0xC6 | $op << 3), Enc::Imm8(1)], 7, $flags),
+            Def::new($mnemonic, &[A, HL_ADDR], &[Byte(0x86 | $op << 3)], 7, $flags),
+            Def::new($mnemonic, &[A, Indexed], &[IndexPrefix, Byte(0x86 | $op << 3), Disp(1)], 19, $flags),
+        ]
+    };
+}
+
+/// The CB-prefixed rotate and shift instructions, `op` selects the operation.
+#[rustfmt::skip]
+macro_rules! rotate {
+    ($mnemonic:literal, $op:literal) => {
+        [
+            Def::new($mnemonic, &[R8], &[Byte(0xCB), Op($op << 3, &[op(0, 0)])], 8, "**0P0*"),
+            Def::new($mnemonic, &[HL_ADDR], &[Byte(0xCB), Byte($op << 3 | 6)], 15, "**0P0*"),
+            Def::new($mnemonic, &[Indexed], &[IndexPrefix, Byte(0xCB), Disp(0), Byte($op << 3 | 6)], 23, "**0P0*"),
+        ]
+    };
+}
+
+/// `bit`, `res` and `set`, which only differ in their base opcode and timing.
+#[rustfmt::skip]
+macro_rules! bit_op {
+    ($mnemonic:literal, $base:literal, $t_states:literal, $t_states_memory:literal, $flags:literal) => {
+        [
+            Def::new($mnemonic, &[Bit, R8], &[Byte(0xCB), Op($base, &[op(0, 3), op(1, 0)])], $t_states, $flags),
+            Def::new($mnemonic, &[Bit, HL_ADDR], &[Byte(0xCB), Op($base | 6, &[op(0, 3)])], $t_states_memory, $flags),
+            Def::new($mnemonic, &[Bit, Indexed], &[IndexPrefix, Byte(0xCB), Disp(1), Op($base | 6, &[op(0, 3)])], $t_states_memory + 8, $flags),
+        ]
+    };
+}
+
+#[rustfmt::skip]
+const LOAD_8: &[Def] = &[
+    Def::new("ld", &[R8, R8], &[Op(0x40, &[op(0, 3), op(1, 0)])], 4, "------"),
+    Def::new("ld", &[R8, Imm8], &[Op(0x06, &[op(0, 3)]), Enc::Imm8(1)], 7, "------"),
+    Def::new("ld", &[R8, HL_ADDR], &[Op(0x46, &[op(0, 3)])], 7, "------"),
+    Def::new("ld", &[R8, Indexed], &[IndexPrefix, Op(0x46, &[op(0, 3)]), Disp(1)], 19, "------"),
+    Def::new("ld", &[HL_ADDR, R8], &[Op(0x70, &[op(1, 0)])], 7, "------"),
+    Def::new("ld", &[Indexed, R8], &[IndexPrefix, Op(0x70, &[op(1, 0)]), Disp(0)], 19, "------"),
+    Def::new("ld", &[HL_ADDR, Imm8], &[Byte(0x36), Enc::Imm8(1)], 10, "------"),
+    Def::new("ld", &[Indexed, Imm8], &[IndexPrefix, Byte(0x36), Disp(0), Enc::Imm8(1)], 19, "------"),
+    Def::new("ld", &[A, BC_ADDR], &[Byte(0x0A)], 7, "------"),
+    Def::new("ld", &[A, DE_ADDR], &[Byte(0x1A)], 7, "------"),
+    Def::new("ld", &[A, Addr16], &[Byte(0x3A), Enc::Imm16(1)], 13, "------"),
+    Def::new("ld", &[BC_ADDR, A], &[Byte(0x02)], 7, "------"),
+    Def::new("ld", &[DE_ADDR, A], &[Byte(0x12)], 7, "------"),
+    Def::new("ld", &[Addr16, A], &[Byte(0x32), Enc::Imm16(0)], 13, "------"),
+    Def::new("ld", &[A, I], &[Byte(0xED), Byte(0x57)], 9, "**0*0-"),
+    Def::new("ld", &[A, R], &[Byte(0xED), Byte(0x5F)], 9, "**0*0-"),
+    Def::new("ld", &[I, A], &[Byte(0xED), Byte(0x47)], 9, "------"),
+    Def::new("ld", &[R, A], &[Byte(0xED), Byte(0x4F)], 9, "------"),
+];
+
+#[rustfmt::skip]
+const LOAD_16: &[Def] = &[
+    Def::new("ld", &[Pair, Imm16], &[Op(0x01, &[op(0, 4)]), Enc::Imm16(1)], 10, "------"),
+    Def::new("ld", &[Index, Imm16], &[IndexPrefix, Byte(0x21), Enc::Imm16(1)], 14, "------"),
+    Def::new("ld", &[HL, Addr16], &[Byte(0x2A), Enc::Imm16(1)], 16, "------"),
+    Def::new("ld", &[Pair, Addr16], &[Byte(0xED), Op(0x4B, &[op(0, 4)]), Enc::Imm16(1)], 20, "------"),
+    Def::new("ld", &[Index, Addr16], &[IndexPrefix, Byte(0x2A), Enc::Imm16(1)], 20, "------"),
+    Def::new("ld", &[Addr16, HL], &[Byte(0x22), Enc::Imm16(0)], 16, "------"),
+    Def::new("ld", &[Addr16, Pair], &[Byte(0xED), Op(0x43, &[op(1, 4)]), Enc::Imm16(0)], 20, "------"),
+    Def::new("ld", &[Addr16, Index], &[IndexPrefix, Byte(0x22), Enc::Imm16(0)], 20, "------"),
+    Def::new("ld", &[SP, HL], &[Byte(0xF9)], 6, "------"),
+    Def::new("ld", &[SP, Index], &[IndexPrefix, Byte(0xF9)], 10, "------"),
+    Def::new("push", &[StackPair], &[Op(0xC5, &[op(0, 4)])], 11, "------"),
+    Def::new("push", &[Index], &[IndexPrefix, Byte(0xE5)], 15, "------"),
+    Def::new("pop", &[StackPair], &[Op(0xC1, &[op(0, 4)])], 10, "------"),
+    Def::new("pop", &[Index], &[IndexPrefix, Byte(0xE1)], 14, "------"),
+];
+
+#[rustfmt::skip]
+const EXCHANGE_AND_BLOCK: &[Def] = &[
+    Def::new("ex", &[DE, HL], &[Byte(0xEB)], 4, "------"),
+    Def::new("ex", &[AF, AF_SHADOW], &[Byte(0x08)], 4, "******"),
+    Def::new("exx", &[], &[Byte(0xD9)], 4, "------"),
+    Def::new("ex", &[SP_ADDR, HL], &[Byte(0xE3)], 19, "------"),
+    Def::new("ex", &[SP_ADDR, Index], &[IndexPrefix, Byte(0xE3)], 23, "------"),
+    Def::new("ldi", &[], &[Byte(0xED), Byte(0xA0)], 16, "--0*0-"),
+    Def::new("ldir", &[], &[Byte(0xED), Byte(0xB0)], 21, "--000-").otherwise(16),
+    Def::new("ldd", &[], &[Byte(0xED), Byte(0xA8)], 16, "--0*0-"),
+    Def::new("lddr", &[], &[Byte(0xED), Byte(0xB8)], 21, "--000-").otherwise(16),
+    Def::new("cpi", &[], &[Byte(0xED), Byte(0xA1)], 16, "****1-"),
+    Def::new("cpir", &[], &[Byte(0xED), Byte(0xB1)], 21, "****1-").otherwise(16),
+    Def::new("cpd", &[], &[Byte(0xED), Byte(0xA9)], 16, "****1-"),
+    Def::new("cpdr", &[], &[Byte(0xED), Byte(0xB9)], 21, "****1-").otherwise(16),
+];
+
+const ALU_8: &[&[Def]] = &[
+    &alu!("add", 0, "***V0*"),
+    &alu!("adc", 1, "***V0*"),
+    &alu!("sub", 2, "***V1*"),
+    &alu!("sbc", 3, "***V1*"),
+    &alu!("and", 4, "**1P00"),
+    &alu!("xor", 5, "**0P00"),
+    &alu!("or", 6, "**0P00"),
+    &alu!("cp", 7, "***V1*"),
+];
+
+#[rustfmt::skip]
+const INC_DEC: &[Def] = &[
+    Def::new("inc", &[R8], &[Op(0x04, &[op(0, 3)])], 4, "***V0-"),
+    Def::new("inc", &[HL_ADDR], &[Byte(0x34)], 11, "***V0-"),
+    Def::new("inc", &[Indexed], &[IndexPrefix, Byte(0x34), Disp(0)], 23, "***V0-"),
+    Def::new("dec", &[R8], &[Op(0x05, &[op(0, 3)])], 4, "***V1-"),
+    Def::new("dec", &[HL_ADDR], &[Byte(0x35)], 11, "***V1-"),
+    Def::new("dec", &[Indexed], &[IndexPrefix, Byte(0x35), Disp(0)], 23, "***V1-"),
+    Def::new("inc", &[Pair], &[Op(0x03, &[op(0, 4)])], 6, "------"),
+    Def::new("inc", &[Index], &[IndexPrefix, Byte(0x23)], 10, "------"),
+    Def::new("dec", &[Pair], &[Op(0x0B, &[op(0, 4)])], 6, "------"),
+    Def::new("dec", &[Index], &[IndexPrefix, Byte(0x2B)], 10, "------"),
+];
+
+#[rustfmt::skip]
+const ALU_16: &[Def] = &[
+    Def::new("add", &[HL, Pair], &[Op(0x09, &[op(1, 4)])], 11, "--*-0*"),
+    Def::new("adc", &[HL, Pair], &[Byte(0xED), Op(0x4A, &[op(1, 4)])], 15, "***V0*"),
+    Def::new("sbc", &[HL, Pair], &[Byte(0xED), Op(0x42, &[op(1, 4)])], 15, "***V1*"),
+    Def::new("add", &[Index, IndexPair], &[IndexPrefix, Op(0x09, &[op(1, 4)])], 15, "--*-0*"),
+];
+
+#[rustfmt::skip]
+const CONTROL: &[Def] = &[
+    Def::new("nop", &[], &[Byte(0x00)], 4, "------"),
+    Def::new("halt", &[], &[Byte(0x76)], 4, "------"),
+    Def::new("hlt", &[], &[Byte(0x76)], 4, "------"),
+    Def::new("di", &[], &[Byte(0xF3)], 4, "------"),
+    Def::new("ei", &[], &[Byte(0xFB)], 4, "------"),
+    Def::new("im", &[InterruptMode], &[Byte(0xED), Op(0x46, &[op(0, 3)])], 8, "------"),
+    Def::new("daa", &[], &[Byte(0x27)], 4, "***P-*"),
+    Def::new("cpl", &[], &[Byte(0x2F)], 4, "--1-1-"),
+    Def::new("neg", &[], &[Byte(0xED), Byte(0x44)], 8, "***V1*"),
+    Def::new("ccf", &[], &[Byte(0x3F)], 4, "--*-0*"),
+    Def::new("scf", &[], &[Byte(0x37)], 4, "--0-01"),
+];
+
+#[rustfmt::skip]
+const ROTATE: &[&[Def]] = &[
+    &[
+        Def::new("rlca", &[], &[Byte(0x07)], 4, "--0-0*"),
+        Def::new("rrca", &[], &[Byte(0x0F)], 4, "--0-0*"),
+        Def::new("rla", &[], &[Byte(0x17)], 4, "--0-0*"),
+        Def::new("rra", &[], &[Byte(0x1F)], 4, "--0-0*"),
+        Def::new("rld", &[], &[Byte(0xED), Byte(0x6F)], 18, "**0P0-"),
+        Def::new("rrd", &[], &[Byte(0xED), Byte(0x67)], 18, "**0P0-"),
+    ],
+    &rotate!("rlc", 0),
+    &rotate!("rrc", 1),
+    &rotate!("rl", 2),
+    &rotate!("rr", 3),
+    &rotate!("sla", 4),
+    &rotate!("sra", 5),
+    &rotate!("srl", 7),
+    &bit_op!("bit", 0x40, 8, 12, "?*1?0-"),
+    &bit_op!("res", 0x80, 8, 15, "------"),
+    &bit_op!("set", 0xC0, 8, 15, "------"),
+];
+
+#[rustfmt::skip]
+const JUMP: &[Def] = &[
+    Def::new("jp", &[Imm16], &[Byte(0xC3), Enc::Imm16(0)], 10, "------"),
+    Def::new("jp", &[Imm16], &[Op(0xC2, &[Condition]), Enc::Imm16(0)], 10, "------").conditional(Conditions::All),
+    Def::new("jp", &[HL_ADDR], &[Byte(0xE9)], 4, "------"),
+    Def::new("jp", &[IndexAddr], &[IndexPrefix, Byte(0xE9)], 8, "------"),
+    Def::new("jr", &[Relative], &[Byte(0x18), Rel(0)], 12, "------"),
+    Def::new("jr", &[Relative], &[Op(0x20, &[Condition]), Rel(0)], 12, "------").conditional(Conditions::ZeroCarry).otherwise(7),
+    Def::new("djnz", &[Relative], &[Byte(0x10), Rel(0)], 13, "------").otherwise(8),
+    Def::new("call", &[Imm16], &[Byte(0xCD), Enc::Imm16(0)], 17, "------"),
+    Def::new("call", &[Imm16], &[Op(0xC4, &[Condition]), Enc::Imm16(0)], 17, "------").conditional(Conditions::All).otherwise(10),
+    Def::new("ret", &[], &[Byte(0xC9)], 10, "------"),
+    Def::new("ret", &[], &[Op(0xC0, &[Condition])], 11, "------").conditional(Conditions::All).otherwise(5),
+    Def::new("reti", &[], &[Byte(0xED), Byte(0x4D)], 14, "------"),
+    Def::new("retn", &[], &[Byte(0xED), Byte(0x45)], 14, "------"),
+    Def::new("rst", &[RstVector], &[Op(0xC7, &[op(0, 0)])], 11, "------"),
+];
+
+#[rustfmt::skip]
+const IO: &[Def] = &[
+    Def::new("in", &[A, Port], &[Byte(0xDB), Enc::Imm8(1)], 11, "------"),
+    Def::new("in", &[R8, C_PORT], &[Byte(0xED), Op(0x40, &[op(0, 3)])], 12, "**0P0-"),
+    Def::new("ini", &[], &[Byte(0xED), Byte(0xA2)], 16, "?*??1?"),
+    Def::new("inir", &[], &[Byte(0xED), Byte(0xB2)], 21, "?1??1?").otherwise(16),
+    Def::new("ind", &[], &[Byte(0xED), Byte(0xAA)], 16, "?*??1?"),
+    Def::new("indr", &[], &[Byte(0xED), Byte(0xBA)], 21, "?1??1?").otherwise(16),
+    Def::new("out", &[Port, A], &[Byte(0xD3), Enc::Imm8(0)], 11, "------"),
+    Def::new("out", &[C_PORT, R8], &[Byte(0xED), Op(0x41, &[op(1, 3)])], 12, "------"),
+    Def::new("outi", &[], &[Byte(0xED), Byte(0xA3)], 16, "?*??1?"),
+    Def::new("otir", &[], &[Byte(0xED), Byte(0xB3)], 21, "?1??1?").otherwise(16),
+    Def::new("outd", &[], &[Byte(0xED), Byte(0xAB)], 16, "?*??1?"),
+    Def::new("otdr", &[], &[Byte(0xED), Byte(0xBB)], 21, "?1??1?").otherwise(16),
+];
+
+/// The arithmetic and logic instructions on index register halves, like `add A, IXH`.
+#[rustfmt::skip]
+macro_rules! alu_index_half {
+    ($mnemonic:literal, $op:literal, $flags:literal) => {
+        undocumented([
+            Def::new($mnemonic, &[IndexHalf], &[IndexPrefix, Op(0x80 | $op << 3, &[op(0, 0)])], 8, $flags),
+            Def::new($mnemonic, &[A, IndexHalf], &[IndexPrefix, Op(0x80 | $op << 3, &[op(1, 0)])], 8, $flags),
+        ])
+    };
+}
+
+/// The DDCB rotates and shifts that also copy the result into a register, like `rlc (IX + 1)*, B`.
+#[rustfmt::skip]
+macro_rules! rotate_copy {
+    ($mnemonic:literal, $op:literal) => {
+        Def::new($mnemonic, &[Indexed, R8], &[IndexPrefix, Byte(0xCB), Disp(0), Op($op << 3, &[op(1, 0)])], 23, "**0P0*").undocumented()
+    };
+}
+
+#[rustfmt::skip]
+const INDEX_HALVES: &[Def] = &undocumented([
+    Def::new("ld", &[R8NoHL, IndexHalf], &[IndexPrefix, Op(0x40, &[op(0, 3), op(1, 0)])], 8, "------"),
+    Def::new("ld", &[IndexHalf, R8NoHL], &[IndexPrefix, Op(0x40, &[op(0, 3), op(1, 0)])], 8, "------"),
+    Def::new("ld", &[IndexHalf, IndexHalf], &[IndexPrefix, Op(0x40, &[op(0, 3), op(1, 0)])], 8, "------"),
+    Def::new("ld", &[IndexHalf, Imm8], &[IndexPrefix, Op(0x06, &[op(0, 3)]), Enc::Imm8(1)], 11, "------"),
+    Def::new("inc", &[IndexHalf], &[IndexPrefix, Op(0x04, &[op(0, 3)])], 8, "***V0-"),
+    Def::new("dec", &[IndexHalf], &[IndexPrefix, Op(0x05, &[op(0, 3)])], 8, "***V1-"),
+]);
+
+const INDEX_HALVES_ALU: &[&[Def]] = &[
+    &alu_index_half!("add", 0, "***V0*"),
+    &alu_index_half!("adc", 1, "***V0*"),
+    &alu_index_half!("sub", 2, "***V1*"),
+    &alu_index_half!("sbc", 3, "***V1*"),
+    &alu_index_half!("and", 4, "**1P00"),
+    &alu_index_half!("xor", 5, "**0P00"),
+    &alu_index_half!("or", 6, "**0P00"),
+    &alu_index_half!("cp", 7, "***V1*"),
+];
+
+#[rustfmt::skip]
+const UNDOCUMENTED: &[&[Def]] = &[
+    &undocumented(rotate!("sll", 6)),
+    &undocumented(rotate!("sl1", 6)),
+    &[
+        Def::new("out", &[C_PORT, Number(0)], &[Byte(0xED), Byte(0x71)], 12, "------").undocumented(),
+        rotate_copy!("rlc", 0),
+        rotate_copy!("rrc", 1),
+        rotate_copy!("rl", 2),
+        rotate_copy!("rr", 3),
+        rotate_copy!("sla", 4),
+        rotate_copy!("sra", 5),
+        rotate_copy!("sll", 6),
+        rotate_copy!("sl1", 6),
+        rotate_copy!("srl", 7),
+        Def::new("res", &[Bit, Indexed, R8], &[IndexPrefix, Byte(0xCB), Disp(1), Op(0x80, &[op(0, 3), op(2, 0)])], 23, "------").undocumented(),
+        Def::new("set", &[Bit, Indexed, R8], &[IndexPrefix, Byte(0xCB), Disp(1), Op(0xC0, &[op(0, 3), op(2, 0)])], 23, "------").undocumented(),
+    ],
+];
+
+/// Every Z80 instruction, the undocumented ones are only allowed with `@undocumented`.
+pub static Z80: InstructionSet = &[
+    LOAD_8,
+    LOAD_16,
+    EXCHANGE_AND_BLOCK,
+    ALU_8[0],
+    ALU_8[1],
+    ALU_8[2],
+    ALU_8[3],
+    ALU_8[4],
+    ALU_8[5],
+    ALU_8[6],
+    ALU_8[7],
+    ALU_16,
+    INC_DEC,
+    CONTROL,
+    ROTATE[0],
+    ROTATE[1],
+    ROTATE[2],
+    ROTATE[3],
+    ROTATE[4],
+    ROTATE[5],
+    ROTATE[6],
+    ROTATE[7],
+    ROTATE[8],
+    ROTATE[9],
+    ROTATE[10],
+    JUMP,
+    IO,
+    INDEX_HALVES,
+    INDEX_HALVES_ALU[0],
+    INDEX_HALVES_ALU[1],
+    INDEX_HALVES_ALU[2],
+    INDEX_HALVES_ALU[3],
+    INDEX_HALVES_ALU[4],
+    INDEX_HALVES_ALU[5],
+    INDEX_HALVES_ALU[6],
+    INDEX_HALVES_ALU[7],
+    UNDOCUMENTED[0],
+    UNDOCUMENTED[1],
+    UNDOCUMENTED[2],
+];
@@ -1,48 +1,210 @@
-use std::{collections::HashMap, ops::Range};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    path::Path,
+};
 
 use crate::{
     tokenizer::{Token, TokenType},
-    CompileError, MultiResult, Span,
+    CompileError, FileId, MultiResult, Sources, Span,
 };
 
-use self::compiler_context::CompilerContext;
+use self::{
+    compiler_context::CompilerContext,
+    impl_sections::BlockEnd,
+    isa::{InstructionDef, Target},
+    types::Register,
+};
 
+/// Unwraps a `Result`, or records the error and skips to the next line.
+macro_rules! try_return {
+    ($self:expr, $call:expr) => {
+        match $call {
+            Ok(v) => v,
+            Err(e) => {
+                $self.errors.push(e);
+                $self.next_reset();
+                return;
+            }
+        }
+    };
+}
+
+mod cartridge;
 mod compiler_context;
 mod impl_helper;
+mod impl_instructions;
+mod impl_modules;
 mod impl_read_tokens;
 mod impl_sections;
+pub mod isa;
+mod output;
 mod types;
 
+pub use output::{CompileOutput, RegionKind, RegionUsage, Symbol, SymbolKind};
+
 struct AllocatedArea {
     pub name: String,
     pub range: Range<u16>,
 }
 
-struct Compiler<'a> {
+/// A file being compiled, borrowed from `Sources` or the text and tokens given to `compile`.
+struct SourceRef<'a> {
+    /// Imports are resolved relative to it, a file compiled on its own has none.
+    path: Option<&'a Path>,
     text: &'a str,
+    tokens: &'a [Token],
+}
+
+/// A name in another module like `vdp::init`, checked to be `pub` once every declaration is known.
+struct ForeignReference {
+    name: String,
+    span: Span,
+}
+
+/// A memory region declared with `@region(name, start, size, kind)`.
+struct Region {
+    name: String,
+    /// Wider than an address, so a region can end at the top of memory.
+    range: Range<u32>,
+    kind: RegionKind,
+}
+
+/// A name bound to a register by a `using` block.
+struct Alias {
+    name: String,
+    register: Register,
+}
+
+/// A subroutine ending in `fallthrough`, checked once every block has its address.
+struct Fallthrough {
+    name: String,
+    span: Span,
+    address: u16,
+    /// The block following it in the source, which execution is expected to land in.
+    next: Option<String>,
+}
+
+/// Where `break` and `continue` jump to in a loop, the addresses are `None` while the loop is measured.
+struct LoopLabels {
+    continue_label: String,
+    continue_address: Option<u16>,
+    break_label: String,
+    break_address: Option<u16>,
+    /// Whether a `break` can leave the loop, so execution can continue after it.
+    broken: bool,
+}
+
+/// A block compiled ahead of time to find its size, so the jumps around it can be chosen before it's written.
+#[derive(Clone)]
+struct MeasuredBlock<'a> {
+    size: u16,
+    end: BlockEnd,
+    /// The tokens following the block.
+    rest: &'a [Token],
+}
+
+/// Where parsing was, to go back to after measuring a block.
+struct Checkpoint<'a> {
+    remaining_tokens: &'a [Token],
+    latest_span: Span,
+    address: u16,
+    errors: usize,
+    write_queue: usize,
+    resolution_queue: usize,
+    last_instruction: Option<&'static InstructionDef>,
+}
+
+type WriteFn = Box<dyn FnOnce(&mut CompilerContext)>;
+type ResolutionFn = Box<dyn Fn(&mut CompilerContext) -> bool>;
+
+struct Compiler<'a> {
+    files: Vec<SourceRef<'a>>,
+    /// Files for `@incbin(...)` and their contents.
+    binaries: Vec<(&'a Path, &'a [u8])>,
     remaining_tokens: &'a [Token],
     latest_span: Span,
     errors: Vec<CompileError>,
+    warnings: Vec<CompileError>,
 
     address: u16,
+    target: Target,
+    /// Whether any code or data has been written, `@target` has to come before it.
+    has_written: bool,
+    /// Set by `@cartridge(...)`, the checksums can only be calculated once the whole ROM is written.
+    has_cartridge_header: bool,
+    /// Set by `@undocumented`, allows instructions missing from the official documentation.
+    allow_undocumented: bool,
 
     allocated_areas: Vec<AllocatedArea>,
+    fallthroughs: Vec<Fallthrough>,
+    /// Declared with `@region(...)`, code and data are only checked against them if there are any.
+    regions: Vec<Region>,
+    /// The kind of every declaration, their values are only known after resolution.
+    symbols: Vec<(String, SymbolKind)>,
+    /// The aliases of the `using` blocks around the current line, innermost last.
+    aliases: Vec<Alias>,
+    /// The loops around the current line, innermost last.
+    loops: Vec<LoopLabels>,
+    /// The latest instruction written, used to check how blocks end.
+    last_instruction: Option<&'static InstructionDef>,
+    /// Values of declarations known while parsing, so the 6502 can use zero page addressing for them.
+    known_values: HashMap<String, u16>,
+    /// Blocks measured ahead of time, by their module, file and the number of tokens left at their start,
+    /// so nested blocks are only measured once.
+    measured_blocks: HashMap<(String, FileId, usize), MeasuredBlock<'a>>,
+
+    /// The module being compiled, declarations in it are named like `vdp::init`. Empty for the first file.
+    namespace: String,
+    /// The file of every imported module, by namespace.
+    modules: HashMap<String, FileId>,
+    /// Files already compiled, or being compiled, so every module is only compiled once.
+    compiled_files: HashSet<FileId>,
+    /// Files included into each module, so including one again, or in a cycle reported by `Sources`, is skipped.
+    included_files: HashSet<(FileId, String)>,
+    /// Declarations marked with `pub`.
+    exports: HashSet<String>,
+    references: Vec<ForeignReference>,
 
     /// Used to properly resolve late-declared identifiers.
-    write_queue: Vec<Box<dyn FnOnce(&mut CompilerContext)>>,
-    resolution_queue: Vec<Box<dyn Fn(&mut CompilerContext) -> bool>>,
+    write_queue: Vec<WriteFn>,
+    resolution_queue: Vec<ResolutionFn>,
 }
 
 impl<'a> Compiler<'a> {
     // The const generic here helps make sure the Compiler.address stays in sync with the future CompilerContext.address
     fn write<const N: usize>(&mut self, f: impl FnOnce(&mut CompilerContext) -> [u8; N] + 'static) {
         self.address += u16::try_from(N).unwrap();
+        self.has_written = true;
         self.write_queue.push(Box::new(|ctx| {
             let data = f(ctx);
             ctx.write(&data);
         }));
     }
 
+    /// Like `write`, for data whose size is only known at runtime, like instructions encoded from a table.
+    fn write_dyn(&mut self, size: u16, f: impl FnOnce(&mut CompilerContext) -> Vec<u8> + 'static) {
+        self.address += size;
+        self.has_written = true;
+        self.write_queue.push(Box::new(move |ctx| {
+            let data = f(ctx);
+            debug_assert_eq!(data.len(), usize::from(size));
+            ctx.write(&data);
+        }));
+    }
+
+    /// Declares `sizeof(name)`, the size in bytes of a subroutine or data.
+    /// A name declared twice is only reported once, for the name itself.
+    fn declare_size(&mut self, name: &str, size: u16, span: Span) {
+        let size_name = size_name(name);
+        self.resolution(move |ctx| {
+            if ctx.get(&size_name).is_none() {
+                ctx.set(&size_name, size, &span);
+            }
+            true
+        });
+    }
+
     // TODO check for collision
     fn set_address(&mut self, new_address: u16) {
         self.address = new_address;
@@ -52,8 +214,8 @@ impl<'a> Compiler<'a> {
 
     fn reserve_area(&mut self, name: &str, new_range: Range<u16>) -> Option<String> {
         for AllocatedArea { name, range } in &self.allocated_areas {
-            let overlaps = (new_range.start >= range.start && new_range.start < range.end)
-                || (new_range.end > range.start && new_range.end <= range.end);
+            // Empty ranges, like an empty subroutine, never overlap anything.
+            let overlaps = new_range.start < range.end && range.start < new_range.end;
 
             if overlaps {
                 return Some(name.to_owned());
@@ -68,6 +230,105 @@ impl<'a> Compiler<'a> {
         None
     }
 
+    /// Reserves `size` bytes at the first free address in `within`, returning the address.
+    /// Allocated areas end exclusively in 16 bits, so the very last byte of memory is never used.
+    fn allocate(&mut self, name: &str, within: &Range<u32>, size: u16) -> Option<u16> {
+        let mut start = u16::try_from(within.start).ok()?;
+        loop {
+            let end = start
+                .checked_add(size)
+                .filter(|end| u32::from(*end) <= within.end)?;
+
+            // Skip past whatever is in the way and try again.
+            match self
+                .allocated_areas
+                .iter()
+                .find(|area| start < area.range.end && area.range.start < end)
+            {
+                Some(area) => start = area.range.end,
+                None => break,
+            }
+        }
+
+        let existing = self.reserve_area(name, start..start + size);
+        debug_assert!(existing.is_none());
+        Some(start)
+    }
+
+    /// Checks that `range` is inside a ROM region, for code and data. Without any regions, everything is allowed.
+    fn check_in_rom(&mut self, what: &str, range: Range<u16>, span: Span) {
+        if self.regions.is_empty() || range.is_empty() {
+            return;
+        }
+
+        let containing = self.regions.iter().find(|region| {
+            region.range.start <= u32::from(range.start) && u32::from(range.end) <= region.range.end
+        });
+
+        let message = match containing {
+            Some(region) if region.kind == RegionKind::Rom => return,
+            Some(region) => format!(
+                "{} is in the {} region '{}', it has to be in a ROM region",
+                what, region.kind, region.name
+            ),
+            None => format!(
+                "{} at ${:04X}..${:04X} isn't inside a ROM region",
+                what, range.start, range.end
+            ),
+        };
+
+        self.errors.push(CompileError { message, span });
+    }
+
+    /// How many bytes of each region are taken, for the report at the end of the compilation.
+    fn region_usage(&self) -> Vec<RegionUsage> {
+        self.regions
+            .iter()
+            .map(|region| {
+                let used = self
+                    .allocated_areas
+                    .iter()
+                    .map(|area| {
+                        let start = u32::from(area.range.start).max(region.range.start);
+                        let end = u32::from(area.range.end).min(region.range.end);
+                        end.saturating_sub(start)
+                    })
+                    .sum::<u32>();
+
+                RegionUsage {
+                    name: region.name.clone(),
+                    kind: region.kind,
+                    start: region.range.start as u16,
+                    size: region.range.end - region.range.start,
+                    used,
+                }
+            })
+            .collect()
+    }
+
+    fn checkpoint(&self) -> Checkpoint<'a> {
+        Checkpoint {
+            remaining_tokens: self.remaining_tokens,
+            latest_span: self.latest_span.clone(),
+            address: self.address,
+            errors: self.errors.len(),
+            write_queue: self.write_queue.len(),
+            resolution_queue: self.resolution_queue.len(),
+            last_instruction: self.last_instruction,
+        }
+    }
+
+    /// Goes back to `checkpoint`, dropping everything written since.
+    fn rewind(&mut self, checkpoint: Checkpoint<'a>) {
+        self.remaining_tokens = checkpoint.remaining_tokens;
+        self.latest_span = checkpoint.latest_span;
+        self.address = checkpoint.address;
+        self.errors.truncate(checkpoint.errors);
+        self.write_queue.truncate(checkpoint.write_queue);
+        self.resolution_queue.truncate(checkpoint.resolution_queue);
+        self.last_instruction = checkpoint.last_instruction;
+    }
+
     fn resolution(&mut self, f: impl Fn(&mut CompilerContext) -> bool + 'static) {
         self.resolution_queue.push(Box::new(f));
     }
@@ -78,6 +339,8 @@ impl<'a> Compiler<'a> {
             TokenType::LabelSpecifier => self.read_label_block(),
             TokenType::DataDeclaration => self.read_data_decl(),
             TokenType::At => self.read_top_level_pragma(),
+            TokenType::Keyword if self.slice(&token.span) == "import" => self.read_import(),
+            TokenType::Keyword if self.slice(&token.span) == "pub" => self.read_public_decl(),
             _ => {
                 self.errors.push(CompileError {
                     message: format!("Unexpected token {:?}", token.ty),
@@ -88,25 +351,69 @@ impl<'a> Compiler<'a> {
         }
     }
 
-    pub fn compile(mut self) -> MultiResult<Vec<u8>> {
+    /// Checks that every `fallthrough` lands in the block following it in the source.
+    fn check_fallthroughs(&mut self) {
+        for fallthrough in std::mem::take(&mut self.fallthroughs) {
+            let landing = self
+                .allocated_areas
+                .iter()
+                .find(|area| area.range.start == fallthrough.address);
+
+            let Some(landing) = landing else {
+                self.errors.push(CompileError {
+                    message: format!(
+                        "Subroutine '{}' falls through to ${:04X}, but no block starts there",
+                        fallthrough.name, fallthrough.address
+                    ),
+                    span: fallthrough.span,
+                });
+                continue;
+            };
+
+            if fallthrough.next.as_ref() == Some(&landing.name) {
+                continue;
+            }
+
+            let message = match &fallthrough.next {
+                Some(next) => format!(
+                    "Subroutine '{}' falls through into '{}' at ${:04X}, not into '{}' which follows it",
+                    fallthrough.name, landing.name, fallthrough.address, next
+                ),
+                None => format!(
+                    "Subroutine '{}' falls through into '{}' at ${:04X}, which doesn't follow it",
+                    fallthrough.name, landing.name, fallthrough.address
+                ),
+            };
+            self.warnings.push(CompileError {
+                message,
+                span: fallthrough.span,
+            });
+        }
+    }
+
+    fn compile(&mut self) -> MultiResult<CompilerContext> {
         self.skip_line_sep();
 
-        while self.remaining_tokens.len() > 0 {
+        while !self.remaining_tokens.is_empty() {
             self.compile_remaining();
             self.skip_line_sep();
         }
 
-        if self.errors.len() > 0 {
-            return MultiResult::Err(self.errors);
+        self.check_fallthroughs();
+        self.check_references();
+
+        if !self.errors.is_empty() {
+            return MultiResult::Err(std::mem::take(&mut self.errors));
         }
 
         let mut ctx = CompilerContext {
             address: 0,
             binary: Vec::new(),
             declarations: HashMap::new(),
+            errors: Vec::new(),
         };
 
-        'resolution_loop: while self.resolution_queue.len() > 0 {
+        'resolution_loop: while !self.resolution_queue.is_empty() {
             let before = self.resolution_queue.len();
             let queue_after = self
                 .resolution_queue
@@ -114,13 +421,14 @@ impl<'a> Compiler<'a> {
                 .filter(|resolution| !resolution(&mut ctx))
                 .collect::<Vec<_>>();
             self.resolution_queue = queue_after;
-            if self.resolution_queue.len() > 0 && self.resolution_queue.len() == before {
+            if !self.resolution_queue.is_empty() && self.resolution_queue.len() == before {
                 for _resolution in &self.resolution_queue {
                     self.errors.push(CompileError {
                         // TODO implement better message using `resolution`
-                        message: format!("Could not resolve a declaration"),
+                        message: "Could not resolve a declaration".to_owned(),
                         // TODO implement correctly
                         span: Span {
+                            file: 0,
                             pos: 0..1,
                             line: 0..1,
                             col: 0..1,
@@ -131,28 +439,130 @@ impl<'a> Compiler<'a> {
             }
         }
 
-        if self.errors.len() > 0 {
-            return MultiResult::Err(self.errors);
+        if !self.errors.is_empty() {
+            return MultiResult::Err(std::mem::take(&mut self.errors));
         }
 
-        for write in self.write_queue {
+        for write in std::mem::take(&mut self.write_queue) {
             write(&mut ctx);
         }
 
-        MultiResult::Ok(ctx.binary)
+        if self.has_cartridge_header {
+            cartridge::finish(&mut ctx.binary);
+        }
+
+        if !ctx.errors.is_empty() {
+            return MultiResult::Err(ctx.errors);
+        }
+
+        MultiResult::Ok(ctx)
     }
 }
 
+/// The hidden name holding the size of `name`, which is also how it's written in the source.
+fn size_name(name: &str) -> String {
+    format!("sizeof({})", name)
+}
+
 pub fn compile(text: &str, tokens: &[Token]) -> MultiResult<Vec<u8>> {
-    Compiler {
+    compile_output(text, tokens).result
+}
+
+/// Like `compile`, also returning warnings and the symbols of every declaration.
+pub fn compile_output(text: &str, tokens: &[Token]) -> CompileOutput {
+    let file = SourceRef {
+        path: None,
         text,
-        remaining_tokens: tokens,
-        latest_span: Span::default(),
-        errors: Vec::new(),
-        write_queue: Vec::new(),
-        resolution_queue: Vec::new(),
-        allocated_areas: Vec::new(),
-        address: 0,
-    }
-    .compile()
+        tokens,
+    };
+    Compiler::new(vec![file], Vec::new()).output()
+}
+
+/// Compiles the first of `sources`, along with the modules it imports and the files it embeds.
+pub fn compile_sources(sources: &Sources) -> CompileOutput {
+    let files = sources
+        .files
+        .iter()
+        .map(|file| SourceRef {
+            path: Some(&file.path),
+            text: &file.text,
+            tokens: &file.tokens,
+        })
+        .collect();
+    let binaries = sources
+        .binaries
+        .iter()
+        .map(|binary| (binary.path.as_path(), binary.data.as_slice()))
+        .collect();
+
+    let mut compiler = Compiler::new(files, binaries);
+    compiler.errors.extend(sources.errors.iter().cloned());
+    compiler.output()
+}
+
+impl<'a> Compiler<'a> {
+    fn new(files: Vec<SourceRef<'a>>, binaries: Vec<(&'a Path, &'a [u8])>) -> Compiler<'a> {
+        Compiler {
+            remaining_tokens: files[0].tokens,
+            files,
+            binaries,
+            latest_span: Span::default(),
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            write_queue: Vec::new(),
+            resolution_queue: Vec::new(),
+            allocated_areas: Vec::new(),
+            fallthroughs: Vec::new(),
+            regions: Vec::new(),
+            symbols: Vec::new(),
+            aliases: Vec::new(),
+            loops: Vec::new(),
+            last_instruction: None,
+            known_values: HashMap::new(),
+            measured_blocks: HashMap::new(),
+            namespace: String::new(),
+            modules: HashMap::new(),
+            compiled_files: HashSet::from([0]),
+            included_files: HashSet::from([(0, String::new())]),
+            exports: HashSet::new(),
+            references: Vec::new(),
+            address: 0,
+            target: Target::default(),
+            has_written: false,
+            has_cartridge_header: false,
+            allow_undocumented: false,
+        }
+    }
+
+    fn output(mut self) -> CompileOutput {
+        let ctx = match self.compile() {
+            MultiResult::Ok(ctx) => ctx,
+            MultiResult::Err(errors) => {
+                return CompileOutput {
+                    result: MultiResult::Err(errors),
+                    warnings: self.warnings,
+                    symbols: Vec::new(),
+                    regions: Vec::new(),
+                }
+            }
+        };
+
+        let regions = self.region_usage();
+        let mut symbols = self
+            .symbols
+            .into_iter()
+            .filter_map(|(name, kind)| {
+                let value = ctx.get(&name)?;
+                Some(Symbol { name, kind, value })
+            })
+            .collect::<Vec<_>>();
+        symbols.sort_by(|a, b| (a.value, &a.name).cmp(&(b.value, &b.name)));
+
+        CompileOutput {
+            result: MultiResult::Ok(ctx.binary),
+            warnings: self.warnings,
+            symbols,
+            regions,
+        }
+    }
 }
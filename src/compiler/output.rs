@@ -0,0 +1,113 @@
+use std::fmt;
+
+use crate::{CompileError, MultiResult};
+
+/// Everything a compilation produces, see `compile_output`.
+#[derive(Debug)]
+pub struct CompileOutput {
+    pub result: MultiResult<Vec<u8>>,
+    /// Problems that don't stop the compilation, like a `fallthrough` into an unexpected block.
+    pub warnings: Vec<CompileError>,
+    /// Every declaration sorted by value, empty if the compilation failed.
+    pub symbols: Vec<Symbol>,
+    /// How much of each `@region` is used, empty if the compilation failed.
+    pub regions: Vec<RegionUsage>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// A `sub` block, the value is its address
+    Subroutine,
+    /// A `def` constant
+    Definition,
+    /// Data declared with `rom`, the value is its address
+    Rom,
+    /// A `var` in RAM, with its size in bytes
+    Variable(u16),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub value: u16,
+}
+
+/// Formats a line of a symbol listing, like `$C000 counter (var, 1 byte)`.
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "${:04X} {}", self.value, self.name)?;
+        match self.kind {
+            SymbolKind::Subroutine => write!(f, " (sub)"),
+            SymbolKind::Definition => write!(f, " (def)"),
+            SymbolKind::Rom => write!(f, " (rom)"),
+            SymbolKind::Variable(1) => write!(f, " (var, 1 byte)"),
+            SymbolKind::Variable(size) => write!(f, " (var, {} bytes)", size),
+        }
+    }
+}
+
+/// What a memory region declared with `@region(...)` holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    /// Code and `rom` data
+    Rom,
+    /// `var`s
+    Ram,
+    /// Memory mapped I/O, nothing can be placed there
+    Mmio,
+}
+
+impl RegionKind {
+    pub const NAMES: &'static [(&'static str, RegionKind)] = &[
+        ("ROM", RegionKind::Rom),
+        ("RAM", RegionKind::Ram),
+        ("MMIO", RegionKind::Mmio),
+    ];
+
+    pub fn from_name(name: &str) -> Option<RegionKind> {
+        RegionKind::NAMES
+            .iter()
+            .find(|(kind_name, _)| *kind_name == name)
+            .map(|(_, kind)| *kind)
+    }
+}
+
+impl fmt::Display for RegionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, _) = RegionKind::NAMES
+            .iter()
+            .find(|(_, kind)| kind == self)
+            .unwrap();
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionUsage {
+    pub name: String,
+    pub kind: RegionKind,
+    pub start: u16,
+    /// Up to $10000, for a region reaching the top of memory.
+    pub size: u32,
+    /// Bytes taken by subroutines, data and variables.
+    pub used: u32,
+}
+
+/// Formats a line of a region report, like `ROM code $0000..$8000: 1024 of 32768 bytes used (3%)`.
+impl fmt::Display for RegionUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = self.used * 100 / self.size.max(1);
+        write!(
+            f,
+            "{} {} ${:04X}..${:04X}: {} of {} bytes used ({}%)",
+            self.kind,
+            self.name,
+            self.start,
+            u32::from(self.start) + self.size,
+            self.used,
+            self.size,
+            percent
+        )
+    }
+}
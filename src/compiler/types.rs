@@ -1,3 +1,9 @@
+use std::fmt;
+
+use crate::{CompileError, Span};
+
+use super::compiler_context::CompilerContext;
+
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum ShortRegister {
     A,
@@ -10,11 +16,50 @@ pub enum ShortRegister {
     L,
     I,
     R,
+
+    /// The undocumented halves of the index registers
+    IXH,
+    IXL,
+    IYH,
+    IYL,
+
+    /// The index registers of the 6502
+    X,
+    Y,
+}
+
+impl ShortRegister {
+    /// The 3-bit `r` field used by most 8-bit instructions, `(HL)` occupies the gap at 6.
+    pub fn code(self) -> Option<u8> {
+        Some(match self {
+            ShortRegister::B => 0,
+            ShortRegister::C => 1,
+            ShortRegister::D => 2,
+            ShortRegister::E => 3,
+            ShortRegister::H => 4,
+            ShortRegister::L => 5,
+            ShortRegister::A => 7,
+            _ => return None,
+        })
+    }
+
+    /// The index register a half belongs to, and the code of H or L whose place it takes behind the prefix.
+    pub fn index_half(self) -> Option<(LongRegister, u8)> {
+        Some(match self {
+            ShortRegister::IXH => (LongRegister::IX, 4),
+            ShortRegister::IXL => (LongRegister::IX, 5),
+            ShortRegister::IYH => (LongRegister::IY, 4),
+            ShortRegister::IYL => (LongRegister::IY, 5),
+            _ => return None,
+        })
+    }
 }
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum LongRegister {
     AF,
+    /// The shadow `AF'`, only accessible through `ex AF, AF'`
+    AFShadow,
     BC,
     DE,
     HL,
@@ -25,22 +70,265 @@ pub enum LongRegister {
     IY,
 }
 
+impl LongRegister {
+    /// The 2-bit `dd`/`ss` field used by 16-bit loads and arithmetic.
+    pub fn pair_code(self) -> Option<u8> {
+        Some(match self {
+            LongRegister::BC => 0,
+            LongRegister::DE => 1,
+            LongRegister::HL => 2,
+            LongRegister::SP => 3,
+            _ => return None,
+        })
+    }
+
+    /// The 2-bit `qq` field used by `push` and `pop`, where AF takes the place of SP.
+    pub fn stack_code(self) -> Option<u8> {
+        Some(match self {
+            LongRegister::BC => 0,
+            LongRegister::DE => 1,
+            LongRegister::HL => 2,
+            LongRegister::AF => 3,
+            _ => return None,
+        })
+    }
+
+    /// Index registers reuse the HL opcodes behind a prefix byte.
+    pub fn index_prefix(self) -> Option<u8> {
+        Some(match self {
+            LongRegister::IX => 0xDD,
+            LongRegister::IY => 0xFD,
+            _ => return None,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum Register {
     Short(ShortRegister),
     Long(LongRegister),
 }
 
+impl Register {
+    /// The 8-bit halves of a register pair, empty for everything else.
+    fn halves(self) -> &'static [ShortRegister] {
+        match self {
+            Register::Long(LongRegister::AF) => &[ShortRegister::A, ShortRegister::F],
+            Register::Long(LongRegister::BC) => &[ShortRegister::B, ShortRegister::C],
+            Register::Long(LongRegister::DE) => &[ShortRegister::D, ShortRegister::E],
+            Register::Long(LongRegister::HL) => &[ShortRegister::H, ShortRegister::L],
+            Register::Long(LongRegister::IX) => &[ShortRegister::IXH, ShortRegister::IXL],
+            Register::Long(LongRegister::IY) => &[ShortRegister::IYH, ShortRegister::IYL],
+            _ => &[],
+        }
+    }
+
+    /// Whether writing one of the registers changes the other, like HL and L.
+    pub fn overlaps(self, other: Register) -> bool {
+        let is_half = |pair: Register, half: Register| match half {
+            Register::Short(short_reg) => pair.halves().contains(&short_reg),
+            Register::Long(_) => false,
+        };
+
+        self == other || is_half(self, other) || is_half(other, self)
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::Short(short_reg) => write!(f, "{:?}", short_reg),
+            Register::Long(LongRegister::AFShadow) => write!(f, "AF'"),
+            Register::Long(long_reg) => write!(f, "{:?}", long_reg),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub enum Value {
+    Literal(u16),
+    Identifier(String),
+}
+
+impl Value {
+    /// The value once every declaration is known, reporting an unknown name at `span`.
+    pub fn resolve(&self, ctx: &mut CompilerContext, span: &Span) -> Option<u16> {
+        match self {
+            Value::Literal(value) => Some(*value),
+            Value::Identifier(name) => ctx.get_label(name, span),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Literal(value) if *value <= 0xFF => write!(f, "${:02X}", value),
+            Value::Literal(value) => write!(f, "${:04X}", value),
+            Value::Identifier(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// The signed offset of an indexed operand, only range checked once it has been resolved
+/// since it may refer to a declaration.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct Displacement {
+    pub value: Value,
+    pub negative: bool,
+    pub span: Span,
+}
+
+impl Displacement {
+    pub fn resolve(&self, ctx: &mut CompilerContext) -> u8 {
+        let Some(value) = self.value.resolve(ctx, &self.span) else {
+            return 0;
+        };
+        let value = value as i32;
+        let value = if self.negative { -value } else { value };
+
+        match i8::try_from(value) {
+            Ok(displacement) => displacement as u8,
+            Err(_) => {
+                ctx.errors.push(CompileError {
+                    message: format!("Displacement '{}' doesn't fit into -128..127", value),
+                    span: self.span.clone(),
+                });
+                0
+            }
+        }
+    }
+}
+
+impl fmt::Display for Displacement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.negative { '-' } else { '+' };
+        write!(f, "{} {}", sign, self.value)
+    }
+}
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum DataTarget {
     Register(Register),
+    /// The memory a register points to, e.g `HL*`
+    RegisterAddress(Register),
+    /// The memory an index register points to, offset by a displacement, e.g `(IX + 4)*`
+    Indexed(LongRegister, Displacement),
+    /// `(HL+)*` and `(HL-)*`, the memory at HL which is stepped afterwards, only on the SM83
+    HLIncrement,
+    HLDecrement,
+    /// The memory at an address offset by a 6502 index register, e.g `($1000 + X)*`
+    IndexedAddress(Value, ShortRegister),
+    /// The memory a zero page pointer at `value + X` points to, e.g `($10 + X)**`
+    IndexedIndirect(Value),
+    /// The memory a zero page pointer points to, offset by Y, e.g `($10* + Y)*`
+    IndirectIndexed(Value),
 
     // TODO these should take some kind of `Expr` object to support more complex expressions
     // TODO e.g `(Table + 10)*`
-    Address(u16),
-    Immediate(u16),
+    Address(Value),
+    Immediate(Value),
+}
+
+impl DataTarget {
+    /// The index register this operand uses, which selects the DD or FD prefix.
+    pub fn index_register(&self) -> Option<LongRegister> {
+        match self {
+            DataTarget::Register(Register::Long(long_reg))
+            | DataTarget::RegisterAddress(Register::Long(long_reg))
+            | DataTarget::Indexed(long_reg, _)
+                if long_reg.index_prefix().is_some() =>
+            {
+                Some(*long_reg)
+            }
+            DataTarget::Register(Register::Short(short_reg)) => {
+                short_reg.index_half().map(|(index_reg, _)| index_reg)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Operands are displayed the way they're written, e.g `HL*` or `(IX + $04)*`.
+impl fmt::Display for DataTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataTarget::Register(register) => write!(f, "{}", register),
+            DataTarget::RegisterAddress(register) => write!(f, "{}*", register),
+            DataTarget::Indexed(index_reg, displacement) => {
+                write!(f, "({:?} {})*", index_reg, displacement)
+            }
+            DataTarget::HLIncrement => write!(f, "(HL+)*"),
+            DataTarget::HLDecrement => write!(f, "(HL-)*"),
+            DataTarget::IndexedAddress(value, index_reg) => {
+                write!(f, "({} + {:?})*", value, index_reg)
+            }
+            DataTarget::IndexedIndirect(value) => write!(f, "({} + X)**", value),
+            DataTarget::IndirectIndexed(value) => write!(f, "({}* + Y)*", value),
+            DataTarget::Address(value) => write!(f, "{}*", value),
+            DataTarget::Immediate(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// The flag conditions of conditional jumps, calls and returns.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Condition {
+    NotZero,
+    Zero,
+    NoCarry,
+    Carry,
+    ParityOdd,
+    ParityEven,
+    Positive,
+    Negative,
+}
+
+impl Condition {
+    pub const NAMES: &'static [(&'static str, Condition)] = &[
+        ("Zero", Condition::Zero),
+        ("Carry", Condition::Carry),
+        ("Overflow", Condition::ParityEven),
+        ("ParityEven", Condition::ParityEven),
+        ("ParityOdd", Condition::ParityOdd),
+        ("Negative", Condition::Negative),
+        ("Positive", Condition::Positive),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Condition> {
+        Condition::NAMES
+            .iter()
+            .find(|(condition_name, _)| *condition_name == name)
+            .map(|(_, condition)| *condition)
+    }
+
+    /// The 3-bit `cc` field, ordered NZ, Z, NC, C, PO, PE, P, M.
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    pub fn negate(self) -> Condition {
+        match self {
+            Condition::NotZero => Condition::Zero,
+            Condition::Zero => Condition::NotZero,
+            Condition::NoCarry => Condition::Carry,
+            Condition::Carry => Condition::NoCarry,
+            Condition::ParityOdd => Condition::ParityEven,
+            Condition::ParityEven => Condition::ParityOdd,
+            Condition::Positive => Condition::Negative,
+            Condition::Negative => Condition::Positive,
+        }
+    }
+}
 
-    // TODO move these into Address and Immediate?
-    IdentifierImmediate(String),
-    IdentifierAddress(String),
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match Condition::NAMES
+            .iter()
+            .find(|(_, condition)| condition == self)
+        {
+            Some((name, _)) => write!(f, "{}", name),
+            None => write!(f, "not({})", self.negate()),
+        }
+    }
 }
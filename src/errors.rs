@@ -1,6 +1,6 @@
 use colored::Colorize;
 
-use crate::Span;
+use crate::{Sources, Span};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -28,6 +28,15 @@ pub struct CompileError {
 }
 
 pub fn print_errors(text: &str, lines: &[usize], errors: Vec<CompileError>, max: usize) {
+    print_at_most(errors, max, |error| print_error(text, lines, error));
+}
+
+/// Like `print_errors`, for errors in any of the files of `sources`.
+pub fn print_source_errors(sources: &Sources, errors: Vec<CompileError>, max: usize) {
+    print_at_most(errors, max, |error| print_source_error(sources, error));
+}
+
+fn print_at_most(errors: Vec<CompileError>, max: usize, print: impl Fn(CompileError)) {
     let mut count = 0;
     let mut reached_max = false;
     let total_error_count = errors.len();
@@ -38,7 +47,7 @@ pub fn print_errors(text: &str, lines: &[usize], errors: Vec<CompileError>, max:
             break;
         }
 
-        print_error(text, lines, error);
+        print(error);
         if count != total_error_count - 1 {
             println!();
         }
@@ -52,25 +61,57 @@ pub fn print_errors(text: &str, lines: &[usize], errors: Vec<CompileError>, max:
 }
 
 pub fn print_error(text: &str, lines: &[usize], error: CompileError) {
-    let CompileError { message, span } = error;
+    eprintln!("{}: {}", "ERROR".red(), error.message);
+    print_span(text, lines, error.span);
+}
+
+/// Prints a warning, which uses `CompileError` for its message and span but doesn't stop the compilation.
+pub fn print_warning(text: &str, lines: &[usize], warning: CompileError) {
+    eprintln!("{}: {}", "WARNING".yellow(), warning.message);
+    print_span(text, lines, warning.span);
+}
 
+/// Like `print_error`, also naming the file the error is in.
+pub fn print_source_error(sources: &Sources, error: CompileError) {
+    eprintln!("{}: {}", "ERROR".red(), error.message);
+    print_source_span(sources, error.span);
+}
+
+/// Like `print_warning`, also naming the file the warning is in.
+pub fn print_source_warning(sources: &Sources, warning: CompileError) {
+    eprintln!("{}: {}", "WARNING".yellow(), warning.message);
+    print_source_span(sources, warning.span);
+}
+
+fn print_source_span(sources: &Sources, span: Span) {
+    let file = &sources.files[span.file];
+    eprintln!(
+        "  {} {}:{}:{}",
+        "-->".blue(),
+        file.path.display(),
+        span.line.start + 1,
+        span.col.start + 1
+    );
+    print_span(&file.text, &file.lines, span);
+}
+
+/// Prints the lines around `span`, pointing at the span itself.
+fn print_span(text: &str, lines: &[usize], span: Span) {
     assert!((span.line.end - span.line.start) == 1);
 
     let line_before = (span.line.start > 0).then(|| span.line.start - 1);
     let line = span.line.start;
-    let line_after = (span.line.end < lines.len()).then(|| span.line.end);
-
-    eprintln!("{}: {}", "ERROR".red(), message);
+    let line_after = (span.line.end < lines.len()).then_some(span.line.end);
 
     // Print line above the error, if possible
     if let Some(line_before) = line_before {
         eprint!("   {} ", "|".blue());
-        eprint_line(text, line_before, &lines);
+        eprint_line(text, line_before, lines);
     }
 
-    // Prints the line with an error
-    eprint!("{:02} {} ", line.to_string().blue(), "|".blue());
-    eprint_line(text, line, &lines);
+    // Prints the line with an error, numbered from 1 like the `-->` header
+    eprint!("{:02} {} ", (line + 1).to_string().blue(), "|".blue());
+    eprint_line(text, line, lines);
 
     // Prints pointer
     eprint!("   {} ", "|".blue());
@@ -85,7 +126,7 @@ pub fn print_error(text: &str, lines: &[usize], error: CompileError) {
     // Print line below the error, if possible
     if let Some(line_after) = line_after {
         eprint!("   {} ", "|".blue());
-        eprint_line(text, line_after, &lines);
+        eprint_line(text, line_after, lines);
     }
 }
 
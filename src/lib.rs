@@ -3,15 +3,22 @@ use std::ops::Range;
 mod char_reader;
 mod compiler;
 mod errors;
+mod sources;
 pub mod tokenizer;
 
 pub(crate) use char_reader::*;
-pub use compiler::compile;
+pub use compiler::{
+    compile, compile_output, compile_sources, isa, CompileOutput, RegionKind, RegionUsage, Symbol,
+    SymbolKind,
+};
 pub use errors::*;
+pub use sources::{BinaryFile, FileId, SourceFile, Sources};
 pub use tokenizer::tokenize;
 
 #[derive(Default, Debug, Clone, Hash, PartialEq, Eq)]
 pub struct Span {
+    /// The file the span is in, always 0 when compiling a single file.
+    pub file: FileId,
     pub pos: Range<usize>,
     pub line: Range<usize>,
     pub col: Range<usize>,
@@ -21,4 +28,14 @@ impl Span {
     pub fn slice<'a>(&self, contents: &'a str) -> &'a str {
         &contents[self.pos.clone()]
     }
+
+    /// Creates a span covering everything from the start of `self` to the end of `end`.
+    pub fn to(&self, end: &Span) -> Span {
+        Span {
+            file: self.file,
+            pos: self.pos.start..end.pos.end,
+            line: self.line.start..end.line.end,
+            col: self.col.start..end.col.end,
+        }
+    }
 }
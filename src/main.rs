@@ -1,12 +1,17 @@
 use std::io::Cursor;
 
 use zircon::{
-    compile, print_error, print_errors,
+    compile_output, compile_sources, print_error, print_errors, print_source_error,
+    print_source_errors, print_source_warning, print_warning,
     tokenizer::{tokenize, TokenType, TokenizerResult},
-    CompileError, Error, MultiResult, Result,
+    CompileError, Error, MultiResult, RegionUsage, Result, Sources, Symbol,
 };
 
 fn main() -> Result<()> {
+    if let Some(path) = std::env::args().nth(1) {
+        return compile_path(&path);
+    }
+
     // let contents = std::fs::read_to_string("main.zir").unwrap();
 
     let contents = r#"
@@ -27,7 +32,7 @@ sub boot {
     let mut has_error = false;
     for error in tokens.iter().filter(|token| token.ty == TokenType::Error) {
         print_error(
-            &contents,
+            contents,
             &lines,
             CompileError {
                 message: "Failed to parse token".to_string(),
@@ -43,15 +48,72 @@ sub boot {
         return Err(Error::Tokenizer);
     }
 
-    let binary = match compile(&contents, &tokens) {
+    let output = compile_output(contents, &tokens);
+    for warning in output.warnings {
+        print_warning(contents, &lines, warning);
+        println!();
+    }
+
+    let binary = match output.result {
         MultiResult::Ok(binary) => binary,
         MultiResult::Err(errors) => {
-            print_errors(&contents, &lines, errors, 1);
+            print_errors(contents, &lines, errors, 1);
 
             return Err(Error::Compile);
         }
     };
 
+    print_output(&binary, output.symbols, output.regions);
+    Ok(())
+}
+
+/// Compiles the file at `path`, along with the modules it imports.
+fn compile_path(path: &str) -> Result<()> {
+    let sources = Sources::load(path)?;
+
+    let mut has_error = false;
+    for error in sources
+        .files
+        .iter()
+        .flat_map(|file| &file.tokens)
+        .filter(|token| token.ty == TokenType::Error)
+    {
+        print_source_error(
+            &sources,
+            CompileError {
+                message: "Failed to parse token".to_string(),
+                span: error.span.clone(),
+            },
+        );
+        println!();
+
+        has_error = true;
+    }
+
+    if has_error {
+        return Err(Error::Tokenizer);
+    }
+
+    let output = compile_sources(&sources);
+    for warning in output.warnings {
+        print_source_warning(&sources, warning);
+        println!();
+    }
+
+    let binary = match output.result {
+        MultiResult::Ok(binary) => binary,
+        MultiResult::Err(errors) => {
+            print_source_errors(&sources, errors, 1);
+
+            return Err(Error::Compile);
+        }
+    };
+
+    print_output(&binary, output.symbols, output.regions);
+    Ok(())
+}
+
+fn print_output(binary: &[u8], symbols: Vec<Symbol>, regions: Vec<RegionUsage>) {
     // let mut out_file = std::fs::OpenOptions::new()
     //     .write(true)
     //     .create(true)
@@ -62,5 +124,11 @@ sub boot {
     // out_file.write_all(&binary).unwrap();
     println!("{:#04X?}", binary);
 
-    Ok(())
+    for symbol in symbols {
+        println!("{}", symbol);
+    }
+
+    for region in regions {
+        println!("{}", region);
+    }
 }
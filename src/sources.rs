@@ -0,0 +1,235 @@
+use std::{
+    io::{self, Cursor},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    tokenizer::{tokenize_file, Token, TokenType, TokenizerResult},
+    CompileError, Span,
+};
+
+/// Which loaded file a span is in, an index into `Sources::files`.
+pub type FileId = usize;
+
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub text: String,
+    /// Where every line starts, for printing errors.
+    pub lines: Vec<usize>,
+    pub tokens: Vec<Token>,
+}
+
+/// A file embedded with `@incbin(...)`.
+pub struct BinaryFile {
+    pub path: PathBuf,
+    pub data: Vec<u8>,
+}
+
+/// A file needed by another one, through `import "path"`, `@include("path")` or `@incbin("path")`.
+struct Dependency {
+    path: String,
+    /// `import`, `include` or `incbin`, for error messages.
+    kind: &'static str,
+    span: Span,
+}
+
+impl SourceFile {
+    /// Every import, include and embedded file in the file.
+    fn dependencies(&self) -> Vec<Dependency> {
+        // A comment runs from `//` to the end of the line, anything in it is ignored.
+        let mut in_comment = false;
+        let tokens = self
+            .tokens
+            .iter()
+            .filter(|token| {
+                match token.ty {
+                    TokenType::CommentLine => in_comment = true,
+                    TokenType::NewLine => in_comment = false,
+                    _ => {}
+                }
+                !in_comment
+            })
+            .collect::<Vec<_>>();
+        let is = |i: usize, ty: TokenType, text: Option<&str>| {
+            tokens.get(i).is_some_and(|token| {
+                token.ty == ty && text.is_none_or(|text| token.span.slice(&self.text) == text)
+            })
+        };
+        // The span covers the tokens `start..=end`, the path is the string at `path`.
+        let dependency = |kind, start: usize, path: usize, end: usize| {
+            let path = tokens[path].span.slice(&self.text);
+            Dependency {
+                path: path[1..path.len() - 1].to_owned(),
+                kind,
+                span: tokens[start].span.to(&tokens[end].span),
+            }
+        };
+
+        let mut dependencies = Vec::new();
+        for i in 0..tokens.len() {
+            if is(i, TokenType::Keyword, Some("import")) && is(i + 1, TokenType::String, None) {
+                dependencies.push(dependency("import", i, i + 1, i + 1));
+            } else if is(i, TokenType::At, None)
+                && is(i + 1, TokenType::Identifier, Some("include"))
+                && is(i + 2, TokenType::OpeningParen, None)
+                && is(i + 3, TokenType::String, None)
+            {
+                let end = if is(i + 4, TokenType::ClosingParen, None) {
+                    i + 4
+                } else {
+                    i + 3
+                };
+                dependencies.push(dependency("include", i, i + 3, end));
+            } else if is(i, TokenType::At, None)
+                && is(i + 1, TokenType::Identifier, Some("incbin"))
+                && is(i + 2, TokenType::OpeningParen, None)
+            {
+                // The name of the data comes first, if there is one.
+                if is(i + 3, TokenType::String, None) {
+                    dependencies.push(dependency("incbin", i, i + 3, i + 3));
+                } else if is(i + 3, TokenType::Identifier, None)
+                    && is(i + 4, TokenType::Comma, None)
+                    && is(i + 5, TokenType::String, None)
+                {
+                    dependencies.push(dependency("incbin", i, i + 5, i + 5));
+                }
+            }
+        }
+        dependencies
+    }
+}
+
+/// A file and every file it imports or includes, loaded before compiling so spans can point into any of them.
+pub struct Sources {
+    /// The file compilation starts from is always the first.
+    pub files: Vec<SourceFile>,
+    pub binaries: Vec<BinaryFile>,
+    /// Files that couldn't be loaded, and import or include cycles.
+    pub errors: Vec<CompileError>,
+}
+
+impl Sources {
+    /// Loads `path`, its imports and includes, and the files it embeds from the file system.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Sources> {
+        Sources::load_with(path, |path| std::fs::read(path))
+    }
+
+    /// Loads `path` and its dependencies using `read`, only failing if `path` itself can't be read.
+    pub fn load_with(
+        path: impl AsRef<Path>,
+        read: impl Fn(&Path) -> io::Result<Vec<u8>>,
+    ) -> io::Result<Sources> {
+        let path = path.as_ref();
+        let mut sources = Sources {
+            files: Vec::new(),
+            binaries: Vec::new(),
+            errors: Vec::new(),
+        };
+
+        let text = read_text(path, &read)?;
+        let file = sources.add(normalize(path), text);
+        sources.load_imports(file, &read, &mut vec![file]);
+
+        Ok(sources)
+    }
+
+    fn add(&mut self, path: PathBuf, text: String) -> FileId {
+        let file = self.files.len();
+        let TokenizerResult { tokens, lines } =
+            tokenize_file(&mut Cursor::new(text.as_bytes()), file)
+                .expect("reading from memory can't fail");
+
+        self.files.push(SourceFile {
+            path,
+            text,
+            lines,
+            tokens,
+        });
+        file
+    }
+
+    /// Loads the imports and includes of `file` depth first, `stack` holds the files whose dependencies are being loaded.
+    fn load_imports(
+        &mut self,
+        file: FileId,
+        read: &impl Fn(&Path) -> io::Result<Vec<u8>>,
+        stack: &mut Vec<FileId>,
+    ) {
+        for dependency in self.files[file].dependencies() {
+            let Dependency { path, kind, span } = dependency;
+            let path = resolve_import(&self.files[file].path, &path);
+
+            if kind == "incbin" {
+                if self.binaries.iter().any(|binary| binary.path == path) {
+                    continue;
+                }
+
+                match read(&path) {
+                    Ok(data) => self.binaries.push(BinaryFile { path, data }),
+                    Err(e) => self.errors.push(CompileError {
+                        message: format!("Can't read '{}': {}", path.display(), e),
+                        span,
+                    }),
+                }
+                continue;
+            }
+
+            if let Some(existing) = self.files.iter().position(|loaded| loaded.path == path) {
+                if let Some(start) = stack.iter().position(|file| *file == existing) {
+                    let cycle = stack[start..]
+                        .iter()
+                        .chain([&existing])
+                        .map(|file| format!("'{}'", self.files[*file].path.display()))
+                        .collect::<Vec<_>>();
+
+                    self.errors.push(CompileError {
+                        message: format!("Cyclic {} {}", kind, cycle.join(" -> ")),
+                        span,
+                    });
+                }
+                continue;
+            }
+
+            match read_text(&path, read) {
+                Ok(text) => {
+                    let imported = self.add(path, text);
+                    stack.push(imported);
+                    self.load_imports(imported, read, stack);
+                    stack.pop();
+                }
+                Err(e) => self.errors.push(CompileError {
+                    message: format!("Can't read '{}': {}", path.display(), e),
+                    span,
+                }),
+            }
+        }
+    }
+}
+
+fn read_text(path: &Path, read: &impl Fn(&Path) -> io::Result<Vec<u8>>) -> io::Result<String> {
+    String::from_utf8(read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// The path of a file imported, included or embedded from the file at `from`, relative to its directory.
+pub(crate) fn resolve_import(from: &Path, import: &str) -> PathBuf {
+    normalize(&from.parent().unwrap_or(Path::new("")).join(import))
+}
+
+/// Folds `.` and `..` into the path without touching the file system, so paths to the same file compare equal.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => normalized.push(".."),
+            },
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
@@ -1,10 +1,10 @@
 use std::io::Read;
 
-use crate::{CharReader, Result, Span};
+use crate::{isa, CharReader, FileId, Result, Span};
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum TokenType {
-    /// lda, sta, etc
+    /// ld, jp, etc, only Z80 mnemonics since the target isn't known yet
     Instruction,
     /// sub
     LabelSpecifier,
@@ -24,6 +24,8 @@ pub enum TokenType {
     HexNumber,
     /// 430
     DecNumber,
+    /// "text"
+    String,
     /// *
     Star,
     /// &
@@ -32,6 +34,10 @@ pub enum TokenType {
     NewLine,
     /// ,
     Comma,
+    /// +
+    Plus,
+    /// -
+    Minus,
     /// \/\/
     CommentLine,
     /// =
@@ -42,6 +48,8 @@ pub enum TokenType {
     Colon,
     /// def, const, var
     DataDeclaration,
+    /// if, not, loop, import, pub, etc
+    Keyword,
     /// Unidentifiable tokens.
     Error,
 }
@@ -57,13 +65,28 @@ pub struct TokenizerResult {
     pub lines: Vec<usize>,
 }
 
-const INSTRUCTIONS: &[&str] = &["ld", "st", "jp"];
 const LABEL_SPECIFIERS: &[&str] = &["sub"];
 const REGISTERS: &[&str] = &[
-    "pc", "sp", "a", "b", "c", "d", "e", "f", "h", "l", "ix", "iy", "i", "r",
+    "pc", "sp", "af", "af'", "bc", "de", "hl", "a", "b", "c", "d", "e", "f", "h", "l", "ix", "iy",
+    "i", "r", "ixh", "ixl", "iyh", "iyl",
+];
+const DATA_DECLARATIONS: &[&str] = &["def", "rom", "var"];
+const KEYWORDS: &[&str] = &[
+    "if",
+    "else",
+    "not",
+    "loop",
+    "while",
+    "repeat",
+    "break",
+    "continue",
+    "fallthrough",
+    "using",
+    "import",
+    "pub",
 ];
-const DATA_DECLARATIONS: &[&str] = &["def", "rom"];
 
+/// Tokenizes a single file, its spans are in file 0.
 pub fn tokenize(reader: &mut impl Read) -> Result<TokenizerResult> {
     let mut reader = CharReader::new(reader);
 
@@ -84,6 +107,8 @@ pub fn tokenize(reader: &mut impl Read) -> Result<TokenizerResult> {
         try_tokenize_single_char!('*', TokenType::Star);
         try_tokenize_single_char!('&', TokenType::Ampersand);
         try_tokenize_single_char!(',', TokenType::Comma);
+        try_tokenize_single_char!('+', TokenType::Plus);
+        try_tokenize_single_char!('-', TokenType::Minus);
         try_tokenize_single_char!('=', TokenType::Equals);
         try_tokenize_single_char!('@', TokenType::At);
         try_tokenize_single_char!('(', TokenType::OpeningParen);
@@ -109,6 +134,7 @@ pub fn tokenize(reader: &mut impl Read) -> Result<TokenizerResult> {
             let end_col = reader.col() + 1;
 
             let span = Span {
+                file: 0,
                 pos: start_pos..end_pos,
                 line: start_line..end_line,
                 col: start_col..end_col,
@@ -134,25 +160,42 @@ pub fn tokenize(reader: &mut impl Read) -> Result<TokenizerResult> {
             continue;
         }
 
+        if char == '"' {
+            tokens.push(read_string_literal(&mut reader)?);
+            continue;
+        }
+
         if char.is_numeric() {
             tokens.push(read_dec_literal(&mut reader)?);
             continue;
         }
 
         if char.is_alphabetic() {
-            let (text, span) = read_ident(&mut reader)?;
+            let (mut text, mut span) = read_ident(&mut reader)?;
+
+            // The shadow register `AF'` is the only name that can contain a quote.
+            if text.eq_ignore_ascii_case("af") && reader.peek_char()? == Some('\'') {
+                let _ = reader.next_char()?;
+                text.push('\'');
+                span.pos.end += 1;
+                span.col.end += 1;
+            }
 
             let text = text.to_lowercase();
             let text = text.as_str();
 
-            let ty = if INSTRUCTIONS.contains(&text) {
-                TokenType::Instruction
-            } else if LABEL_SPECIFIERS.contains(&text) {
+            // `sub` is also a mnemonic, the compiler decides which one it is by context.
+            // Mnemonics of other targets are identifiers, so they can still be used as names on the Z80.
+            let ty = if LABEL_SPECIFIERS.contains(&text) {
                 TokenType::LabelSpecifier
+            } else if isa::is_mnemonic(isa::Z80, text) {
+                TokenType::Instruction
             } else if REGISTERS.contains(&text) {
                 TokenType::Register
             } else if DATA_DECLARATIONS.contains(&text) {
                 TokenType::DataDeclaration
+            } else if KEYWORDS.contains(&text) {
+                TokenType::Keyword
             } else {
                 TokenType::Identifier
             };
@@ -173,6 +216,15 @@ pub fn tokenize(reader: &mut impl Read) -> Result<TokenizerResult> {
     })
 }
 
+/// Like `tokenize`, for one of several files compiled together.
+pub fn tokenize_file(reader: &mut impl Read, file: FileId) -> Result<TokenizerResult> {
+    let mut result = tokenize(reader)?;
+    for token in &mut result.tokens {
+        token.span.file = file;
+    }
+    Ok(result)
+}
+
 fn try_tokenize_single_char(
     reader: &mut CharReader<impl Read>,
     target: char,
@@ -184,6 +236,7 @@ fn try_tokenize_single_char(
             return Ok(Some(Token {
                 ty,
                 span: Span {
+                    file: 0,
                     pos: reader.pos()..(reader.pos() + 1),
                     line: reader.line()..(reader.line() + 1),
                     col: reader.col()..(reader.col() + 1),
@@ -213,6 +266,7 @@ fn read_unidentifiable(reader: &mut CharReader<impl Read>) -> Result<Span> {
     let end_col = reader.col() + 1;
 
     Ok(Span {
+        file: 0,
         pos: start_pos..end_pos,
         line: start_line..end_line,
         col: start_col..end_col,
@@ -264,6 +318,7 @@ fn read_ident(reader: &mut CharReader<impl Read>) -> Result<(String, Span)> {
     Ok((
         text,
         Span {
+            file: 0,
             pos: start_pos..end_pos,
             line: start_line..end_line,
             col: start_col..end_col,
@@ -294,6 +349,49 @@ fn read_hex_literal(reader: &mut CharReader<impl Read>) -> Result<Token> {
     Ok(Token {
         ty: TokenType::HexNumber,
         span: Span {
+            file: 0,
+            pos: start_pos..end_pos,
+            line: start_line..end_line,
+            col: start_col..end_col,
+        },
+    })
+}
+
+/// Reads a string up to the closing quote, strings can't span multiple lines.
+fn read_string_literal(reader: &mut CharReader<impl Read>) -> Result<Token> {
+    assert_eq!(reader.next_char()?.unwrap(), '"');
+
+    let start_pos = reader.pos();
+    let start_line = reader.line();
+    let start_col = reader.col();
+
+    let mut terminated = false;
+    while let Some(char) = reader.peek_char()? {
+        if char == '\n' {
+            break;
+        }
+
+        // Advance the position.
+        let _ = reader.next_char()?;
+
+        if char == '"' {
+            terminated = true;
+            break;
+        }
+    }
+
+    let end_pos = reader.pos() + 1;
+    let end_line = reader.line() + 1;
+    let end_col = reader.col() + 1;
+
+    Ok(Token {
+        ty: if terminated {
+            TokenType::String
+        } else {
+            TokenType::Error
+        },
+        span: Span {
+            file: 0,
             pos: start_pos..end_pos,
             line: start_line..end_line,
             col: start_col..end_col,
@@ -324,6 +422,7 @@ fn read_dec_literal(reader: &mut CharReader<impl Read>) -> Result<Token> {
     Ok(Token {
         ty: TokenType::DecNumber,
         span: Span {
+            file: 0,
             pos: start_pos..end_pos,
             line: start_line..end_line,
             col: start_col..end_col,
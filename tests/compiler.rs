@@ -0,0 +1,2321 @@
+use std::{
+    io::{self, Cursor},
+    path::Path,
+};
+
+use zircon::{
+    compile, compile_output, compile_sources, print_errors, print_source_errors,
+    tokenizer::{tokenize, TokenizerResult},
+    CompileError, CompileOutput, MultiResult, Sources,
+};
+
+fn compile_str(text: &str) -> MultiResult<Vec<u8>> {
+    let TokenizerResult { tokens, lines: _ } = tokenize(&mut Cursor::new(text.as_bytes())).unwrap();
+    compile(text, &tokens)
+}
+
+fn compile_ok(text: &str) -> Vec<u8> {
+    match compile_str(text) {
+        MultiResult::Ok(binary) => binary,
+        MultiResult::Err(errors) => panic!("Expected success, got {:#?}", errors),
+    }
+}
+
+fn compile_err(text: &str) -> Vec<CompileError> {
+    match compile_str(text) {
+        MultiResult::Ok(binary) => panic!("Expected errors, got {:#04X?}", binary),
+        MultiResult::Err(errors) => errors,
+    }
+}
+
+/// Compiles the first of `files` with the others available to import, by path.
+fn compile_files(files: &[(&str, &str)]) -> CompileOutput {
+    compile_files_with_binaries(files, &[])
+}
+
+fn compile_files_with_binaries(
+    files: &[(&str, &str)],
+    binaries: &[(&str, &[u8])],
+) -> CompileOutput {
+    let sources = Sources::load_with(files[0].0, |path| {
+        let text = files.iter().map(|(name, text)| (*name, text.as_bytes()));
+        text.chain(binaries.iter().copied())
+            .find(|(name, _)| Path::new(name) == path)
+            .map(|(_, data)| data.to_vec())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not found"))
+    })
+    .unwrap();
+    compile_sources(&sources)
+}
+
+#[test]
+fn compiler_ld_8bit() {
+    let binary = compile_ok(
+        r#"
+def Target = $6000
+sub boot {
+    ld B, C
+    ld A, L
+    ld D, $12
+    ld E, HL*
+    ld HL*, A
+    ld HL*, $34
+    ld A, BC*
+    ld A, DE*
+    ld BC*, A
+    ld DE*, A
+    ld A, $1234*
+    ld A, Target*
+    ld $1234*, A
+    ld A, I
+    ld A, R
+    ld I, A
+    ld R, A
+    ret
+}
+"#,
+    );
+
+    assert_eq!(
+        binary,
+        vec![
+            0x41, 0x7D, 0x16, 0x12, 0x5E, 0x77, 0x36, 0x34, 0x0A, 0x1A, 0x02, 0x12, 0x3A, 0x34,
+            0x12, 0x3A, 0x00, 0x60, 0x32, 0x34, 0x12, 0xED, 0x57, 0xED, 0x5F, 0xED, 0x47, 0xED,
+            0x4F, 0xC9,
+        ]
+    );
+}
+
+#[test]
+fn compiler_ld_8bit_invalid() {
+    let errors = compile_err(
+        r#"
+sub boot {
+    ld B, I
+    ret
+}
+"#,
+    );
+    assert_eq!(errors.len(), 1);
+
+    let errors = compile_err(
+        r#"
+sub boot {
+    ld B, $100
+    ret
+}
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "Number '256' is too big to fit into the B register"
+    );
+    assert_eq!(errors[0].span.col, 10..14);
+}
+
+#[test]
+fn compiler_ld_16bit_and_stack() {
+    let binary = compile_ok(
+        r#"
+def StackTop = $FFFE
+sub boot {
+    ld SP, StackTop
+    ld BC, $1234
+    ld IX, $5678
+    ld HL, $4000*
+    ld DE, $4000*
+    ld IY, $4000*
+    ld $4000*, HL
+    ld $4000*, SP
+    ld $4000*, IX
+    ld SP, HL
+    ld SP, IY
+    push AF
+    push IX
+    pop DE
+    pop IY
+    ret
+}
+"#,
+    );
+
+    assert_eq!(
+        binary,
+        vec![
+            0x31, 0xFE, 0xFF, 0x01, 0x34, 0x12, 0xDD, 0x21, 0x78, 0x56, 0x2A, 0x00, 0x40, 0xED,
+            0x5B, 0x00, 0x40, 0xFD, 0x2A, 0x00, 0x40, 0x22, 0x00, 0x40, 0xED, 0x73, 0x00, 0x40,
+            0xDD, 0x22, 0x00, 0x40, 0xF9, 0xFD, 0xF9, 0xF5, 0xDD, 0xE5, 0xD1, 0xFD, 0xE1, 0xC9,
+        ]
+    );
+}
+
+#[test]
+fn compiler_push_invalid() {
+    let errors = compile_err(
+        r#"
+sub boot {
+    push SP
+}
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "Invalid operand 'SP' for 'push', expected one of BC, DE, HL, AF, IX or IY"
+    );
+    assert_eq!(errors[0].span.col, 9..11);
+}
+
+#[test]
+fn compiler_alu_8bit() {
+    let binary = compile_ok(
+        r#"
+sub boot {
+    add A, B
+    adc A, $10
+    sub C
+    sbc A, HL*
+    and (IX + 4)*
+    xor (IY - 2)*
+    or A
+    cp $FF
+    inc D
+    dec HL*
+    inc (IX)*
+    ret
+}
+"#,
+    );
+
+    assert_eq!(
+        binary,
+        vec![
+            0x80, 0xCE, 0x10, 0x91, 0x9E, 0xDD, 0xA6, 0x04, 0xFD, 0xAE, 0xFE, 0xB7, 0xFE, 0xFF,
+            0x14, 0x35, 0xDD, 0x34, 0x00, 0xC9,
+        ]
+    );
+}
+
+#[test]
+fn compiler_alu_8bit_invalid() {
+    let errors = compile_err(
+        r#"
+sub boot {
+    and B, C
+    ret
+}
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "Invalid destination 'B' for 'and', expected A"
+    );
+    assert_eq!(errors[0].span.col, 8..9);
+
+    let errors = compile_err(
+        r#"
+sub boot {
+    cp (IX + 200)*
+    ret
+}
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "Displacement '200' doesn't fit into -128..127"
+    );
+    assert_eq!(errors[0].span.col, 13..16);
+}
+
+#[test]
+fn compiler_alu_16bit() {
+    let binary = compile_ok(
+        r#"
+sub boot {
+    add HL, BC
+    add HL, HL
+    adc HL, DE
+    sbc HL, SP
+    add IX, BC
+    add IX, IX
+    add IY, SP
+    inc BC
+    dec SP
+    inc IX
+    dec IY
+    ret
+}
+"#,
+    );
+
+    assert_eq!(
+        binary,
+        vec![
+            0x09, 0x29, 0xED, 0x5A, 0xED, 0x72, 0xDD, 0x09, 0xDD, 0x29, 0xFD, 0x39, 0x03, 0x3B,
+            0xDD, 0x23, 0xFD, 0x2B, 0xC9,
+        ]
+    );
+}
+
+#[test]
+fn compiler_alu_16bit_invalid() {
+    let errors = compile_err(
+        r#"
+sub boot {
+    add DE, BC
+}
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "Invalid destination 'DE' for 'add', expected one of A, HL, IX or IY"
+    );
+    assert_eq!(errors[0].span.col, 8..10);
+
+    let errors = compile_err(
+        r#"
+sub boot {
+    add IX, HL
+}
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "Invalid source 'HL' for 'add IX', expected one of BC, DE, IX or SP"
+    );
+    assert_eq!(errors[0].span.col, 12..14);
+
+    let errors = compile_err(
+        r#"
+sub boot {
+    sbc IY, BC
+}
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "Invalid destination 'IY' for 'sbc', expected A or HL"
+    );
+}
+
+#[test]
+fn compiler_rotate_and_bit() {
+    let binary = compile_ok(
+        r#"
+sub boot {
+    rlca
+    rra
+    rlc B
+    rr HL*
+    sla (IX + 1)*
+    srl A
+    bit 7, H
+    set 0, HL*
+    res 3, (IY - 1)*
+    ret
+}
+"#,
+    );
+
+    assert_eq!(
+        binary,
+        vec![
+            0x07, 0x1F, 0xCB, 0x00, 0xCB, 0x1E, 0xDD, 0xCB, 0x01, 0x26, 0xCB, 0x3F, 0xCB, 0x7C,
+            0xCB, 0xC6, 0xFD, 0xCB, 0xFF, 0x9E, 0xC9,
+        ]
+    );
+}
+
+#[test]
+fn compiler_bit_index_invalid() {
+    let errors = compile_err(
+        r#"
+sub boot {
+    bit 8, A
+}
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "Expected a bit index from 0 to 7, found '8'"
+    );
+    assert_eq!(errors[0].span.col, 8..9);
+}
+
+#[test]
+fn compiler_conditions() {
+    let binary = compile_ok(
+        r#"
+sub boot {
+    jp boot if Zero
+    jp boot if not(Carry)
+    jp boot if Overflow
+    jp boot if not(Negative)
+    call boot
+    call boot if ParityOdd
+    ret if not(Zero)
+    ret
+}
+"#,
+    );
+
+    assert_eq!(
+        binary,
+        vec![
+            0xCA, 0x00, 0x00, 0xD2, 0x00, 0x00, 0xEA, 0x00, 0x00, 0xF2, 0x00, 0x00, 0xCD, 0x00,
+            0x00, 0xE4, 0x00, 0x00, 0xC0, 0xC9,
+        ]
+    );
+}
+
+#[test]
+fn compiler_unknown_condition() {
+    let errors = compile_err(
+        r#"
+sub boot {
+    jp boot if not(Zer)
+}
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "Unknown condition 'Zer', expected one of Zero, Carry, Overflow, ParityEven, ParityOdd, Negative, Positive"
+    );
+    assert_eq!(errors[0].span.col, 19..22);
+}
+
+#[test]
+fn compiler_relative_jumps() {
+    let binary = compile_ok(
+        r#"
+sub boot {
+    djnz boot
+    jr boot if not(Zero)
+    jr next if Carry
+    jr next
+}
+
+sub next {
+    jr boot
+}
+"#,
+    );
+
+    assert_eq!(
+        binary,
+        vec![0x10, 0xFE, 0x20, 0xFC, 0x38, 0x02, 0x18, 0x00, 0x18, 0xF6]
+    );
+}
+
+#[test]
+fn compiler_relative_jump_out_of_range() {
+    let errors = compile_err(
+        r#"
+sub boot {
+    jr far
+}
+
+@origin($0100)
+sub far {
+    jr far if Negative
+}
+"#,
+    );
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        errors[0].message,
+        "'jr' can only be conditional on Zero or Carry"
+    );
+    assert_eq!(errors[0].span.col, 11..22);
+
+    let errors = compile_err(
+        r#"
+sub boot {
+    jr far
+}
+
+@origin($0100)
+sub far {
+    ret
+}
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "'jr' at $0000 can't reach 'far' at $0100, the displacement 254 doesn't fit into -128..127"
+    );
+    assert_eq!(errors[0].span.col, 4..10);
+    assert_eq!(errors[0].span.line, 2..3);
+}
+
+#[test]
+fn compiler_call_and_return() {
+    let binary = compile_ok(
+        r#"
+sub boot {
+    call handler
+    rst $00
+    rst $38
+    ret
+}
+
+sub handler {
+    reti
+    retn
+}
+"#,
+    );
+
+    assert_eq!(
+        binary,
+        vec![0xCD, 0x06, 0x00, 0xC7, 0xFF, 0xC9, 0xED, 0x4D, 0xED, 0x45]
+    );
+}
+
+#[test]
+fn compiler_call_invalid() {
+    let errors = compile_err(
+        r#"
+sub boot {
+    rst $09
+    ret
+}
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "Invalid restart vector '$09', expected one of $00, $08, $10, $18, $20, $28, $30 or $38"
+    );
+    assert_eq!(errors[0].span.col, 8..11);
+
+    let errors = compile_err(
+        r#"
+sub boot {
+    call missing
+    ret
+}
+"#,
+    );
+    assert_eq!(errors[0].message, "Unknown label 'missing'");
+    assert_eq!(errors[0].span.line, 2..3);
+    assert_eq!(errors[0].span.col, 9..16);
+}
+
+#[test]
+fn compiler_block_and_io() {
+    let binary = compile_ok(
+        r#"
+def VdpPort = $BE
+sub boot {
+    ldir
+    lddr
+    cpi
+    in B, C*
+    in A, $10*
+    out C*, E
+    out VdpPort*, A
+    otir
+    ind
+    ret
+}
+"#,
+    );
+
+    assert_eq!(
+        binary,
+        vec![
+            0xED, 0xB0, 0xED, 0xB8, 0xED, 0xA1, 0xED, 0x40, 0xDB, 0x10, 0xED, 0x59, 0xD3, 0xBE,
+            0xED, 0xB3, 0xED, 0xAA, 0xC9,
+        ]
+    );
+}
+
+#[test]
+fn compiler_io_invalid() {
+    let errors = compile_err(
+        r#"
+sub boot {
+    out $100*, A
+    ret
+}
+"#,
+    );
+    assert_eq!(errors[0].message, "Port '$0100' doesn't fit into 8 bits");
+    assert_eq!(errors[0].span.col, 8..13);
+
+    let errors = compile_err(
+        r#"
+sub boot {
+    in B, $10*
+    ret
+}
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "Invalid source '$10*' for 'in B', expected C*"
+    );
+}
+
+#[test]
+fn compiler_control_and_exchange() {
+    let binary = compile_ok(
+        r#"
+sub boot {
+    di
+    im 1
+    ei
+    nop
+    neg
+    cpl
+    ccf
+    scf
+    daa
+    ex DE, HL
+    ex AF, AF'
+    ex SP*, HL
+    ex SP*, IY
+    exx
+    halt
+}
+"#,
+    );
+
+    assert_eq!(
+        binary,
+        vec![
+            0xF3, 0xED, 0x56, 0xFB, 0x00, 0xED, 0x44, 0x2F, 0x3F, 0x37, 0x27, 0xEB, 0x08, 0xE3,
+            0xFD, 0xE3, 0xD9, 0x76,
+        ]
+    );
+}
+
+#[test]
+fn compiler_control_invalid() {
+    let errors = compile_err(
+        r#"
+sub boot {
+    im 3
+}
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "Invalid interrupt mode '3', expected 0, 1 or 2"
+    );
+
+    let errors = compile_err(
+        r#"
+sub boot {
+    ex HL, DE
+}
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "Invalid destination 'HL' for 'ex', expected one of DE, AF or SP*"
+    );
+    assert_eq!(errors[0].span.col, 7..9);
+}
+
+#[test]
+fn compiler_indexed() {
+    let binary = compile_ok(
+        r#"
+def Next = 2
+def Flags = $7F
+sub boot {
+    ld B, (IX + Next)*
+    ld (IY - Next)*, A
+    ld (IX)*, $55
+    ld (IX + Flags)*, $AA
+    inc (IY + Next)*
+    bit 1, (IX - 128)*
+    ret
+}
+"#,
+    );
+
+    assert_eq!(
+        binary,
+        vec![
+            0xDD, 0x46, 0x02, 0xFD, 0x77, 0xFE, 0xDD, 0x36, 0x00, 0x55, 0xDD, 0x36, 0x7F, 0xAA,
+            0xFD, 0x34, 0x02, 0xDD, 0xCB, 0x80, 0x4E, 0xC9,
+        ]
+    );
+}
+
+#[test]
+fn compiler_indexed_out_of_range() {
+    let errors = compile_err(
+        r#"
+def Offset = 128
+sub boot {
+    ld A, (IX + Offset)*
+    ld (IY - Offset)*, A
+    ret
+}
+"#,
+    );
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        errors[0].message,
+        "Displacement '128' doesn't fit into -128..127"
+    );
+    assert_eq!(errors[0].span.line, 3..4);
+    assert_eq!(errors[0].span.col, 16..22);
+}
+
+#[test]
+fn compiler_indexed_unknown() {
+    let errors = compile_err(
+        r#"
+sub boot {
+    ld (IX + Missing)*, A
+    ret
+}
+"#,
+    );
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].message, "Unknown label 'Missing'");
+    assert_eq!(errors[0].span.line, 2..3);
+    assert_eq!(errors[0].span.col, 13..20);
+}
+
+#[test]
+fn compiler_table_forms() {
+    let binary = compile_ok(
+        r#"
+def Value = $42
+sub boot {
+    ld A, Value
+    cp Value
+    jp HL*
+    jp IY*
+    rld
+    rrd
+    hlt
+}
+"#,
+    );
+
+    assert_eq!(
+        binary,
+        vec![0x3E, 0x42, 0xFE, 0x42, 0xE9, 0xFD, 0xE9, 0xED, 0x6F, 0xED, 0x67, 0x76]
+    );
+}
+
+#[test]
+fn compiler_table_invalid() {
+    let errors = compile_err(
+        r#"
+sub boot {
+    add A, B, C
+    ret
+}
+"#,
+    );
+    assert_eq!(errors[0].message, "'add' expects 1 or 2 operands, found 3");
+    assert_eq!(errors[0].span.col, 4..15);
+
+    let errors = compile_err(
+        r#"
+def Big = $1234
+sub boot {
+    ld C, Big
+    ret
+}
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "Number '4660' is too big to fit into the C register"
+    );
+    assert_eq!(errors[0].span.col, 10..13);
+
+    let errors = compile_err(
+        r#"
+sub boot {
+    djnz boot if Zero
+    ret
+}
+"#,
+    );
+    assert_eq!(errors[0].message, "'djnz' can't be conditional");
+    assert_eq!(errors[0].span.col, 14..21);
+}
+
+#[test]
+fn compiler_isa_table() {
+    let forms = zircon::isa::Z80
+        .iter()
+        .flat_map(|group| group.iter())
+        .filter(|def| def.mnemonic == "jr")
+        .collect::<Vec<_>>();
+
+    assert_eq!(forms.len(), 2);
+    assert!(forms.iter().all(|def| def.size() == 2));
+    assert_eq!(forms[0].t_states, (12, 12));
+    assert_eq!(forms[1].t_states, (12, 7));
+    assert_eq!(forms[1].flags, "------");
+}
+
+#[test]
+fn compiler_undocumented() {
+    let binary = compile_ok(
+        r#"
+@undocumented
+sub boot {
+    ld B, IXH
+    ld IYL, A
+    ld IXH, IXL
+    ld IYH, $12
+    add A, IXL
+    cp IYH
+    inc IXL
+    sll B
+    sl1 (IX + 2)*
+    out C*, 0
+    rlc (IY + 1)*, B
+    set 2, (IX - 1)*, A
+    ret
+}
+"#,
+    );
+
+    assert_eq!(
+        binary,
+        vec![
+            0xDD, 0x44, 0xFD, 0x6F, 0xDD, 0x65, 0xFD, 0x26, 0x12, 0xDD, 0x85, 0xFD, 0xBC, 0xDD,
+            0x2C, 0xCB, 0x30, 0xDD, 0xCB, 0x02, 0x36, 0xED, 0x71, 0xFD, 0xCB, 0x01, 0x00, 0xDD,
+            0xCB, 0xFF, 0xD7, 0xC9,
+        ]
+    );
+}
+
+#[test]
+fn compiler_undocumented_invalid() {
+    let errors = compile_err(
+        r#"
+sub boot {
+    sll B
+    ld A, IXH
+}
+"#,
+    );
+    assert_eq!(errors.len(), 2);
+    assert_eq!(
+        errors[0].message,
+        "'sll B' is an undocumented instruction, enable undocumented instructions with '@undocumented'"
+    );
+    assert_eq!(errors[0].span.col, 4..9);
+    assert_eq!(
+        errors[1].message,
+        "'ld A, IXH' is an undocumented instruction, enable undocumented instructions with '@undocumented'"
+    );
+
+    let errors = compile_err(
+        r#"
+@undocumented
+sub boot {
+    ld IXH, IYL
+}
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "Invalid source 'IYL' for 'ld IXH', expected one of B, C, D, E, A, IXH, IXL or an 8-bit number"
+    );
+    assert_eq!(errors[0].span.col, 12..15);
+}
+
+#[test]
+fn compiler_i8080() {
+    let binary = compile_ok(
+        r#"
+@target(i8080)
+sub boot {
+    ld A, $10
+    ld HL, $4000*
+    ex DE, HL
+    ex SP*, HL
+    add A, B
+    jp boot if ParityEven
+    jp HL*
+}
+"#,
+    );
+
+    assert_eq!(
+        binary,
+        vec![0x3E, 0x10, 0x2A, 0x00, 0x40, 0xEB, 0xE3, 0x80, 0xEA, 0x00, 0x00, 0xE9]
+    );
+}
+
+#[test]
+fn compiler_i8080_invalid() {
+    let errors = compile_err(
+        r#"
+@target(i8080)
+sub boot {
+    jr boot
+    ld A, (IX + 1)*
+    bit 0, A
+    ld BC, $4000*
+    exx
+    jp boot if Overflow
+}
+"#,
+    );
+    let messages = errors
+        .iter()
+        .map(|error| error.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        vec![
+            "'jr boot' isn't available on the 8080, it has no relative jumps",
+            "'ld A, (IX + 1)*' isn't available on the 8080, it has no IX or IY registers",
+            "'bit 0, A' isn't available on the 8080, it has no CB-prefixed instructions",
+            "'ld BC, $4000*' isn't available on the 8080, it has no ED-prefixed instructions",
+            "'exx' isn't available on the 8080, it has no shadow registers",
+            "The 8080 has no overflow flag, P/V always holds the parity, use ParityEven instead",
+        ]
+    );
+    assert_eq!(errors[0].span.col, 4..11);
+    assert_eq!(errors[5].span.col, 15..23);
+
+    let errors = compile_err(
+        r#"
+@target(i8086)
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "Unknown target 'i8086', expected one of z80, i8080, sm83, mos6502"
+    );
+
+    let errors = compile_err(
+        r#"
+sub boot {
+    ret
+}
+@target(i8080)
+"#,
+    );
+    assert_eq!(errors[0].message, "'@target' has to come before any code");
+}
+
+#[test]
+fn compiler_i8080_flags() {
+    let add = zircon::isa::Z80
+        .iter()
+        .flat_map(|group| group.iter())
+        .find(|def| def.mnemonic == "add")
+        .unwrap();
+
+    assert_eq!(add.flags_for(zircon::isa::Target::Z80), "***V0*");
+    assert_eq!(add.flags_for(zircon::isa::Target::I8080), "***P0*");
+}
+
+#[test]
+fn compiler_sm83() {
+    let binary = compile_ok(
+        r#"
+@target(sm83)
+@origin($0150)
+sub boot {
+    ldh A, $FF44*
+    ldh $40*, A
+    ldh A, C*
+    ld (HL+)*, A
+    ld A, (HL-)*
+    swap B
+    stop
+    ld $C000*, SP
+    jp boot if not(Zero)
+    jr boot
+    reti
+}
+"#,
+    );
+
+    assert_eq!(
+        binary[0x150..],
+        [
+            0xF0, 0x44, 0xE0, 0x40, 0xF2, 0x22, 0x3A, 0xCB, 0x30, 0x10, 0x00, 0x08, 0x00, 0xC0,
+            0xC2, 0x50, 0x01, 0x18, 0xED, 0xD9,
+        ]
+    );
+
+    // Moving the address doesn't write anything, so the target can still be picked.
+    let binary = compile_ok(
+        r#"
+@origin($0100)
+@target(sm83)
+sub boot {
+    swap B
+    stop
+    ret
+}
+"#,
+    );
+    assert_eq!(binary[0x100..], [0xCB, 0x30, 0x10, 0x00, 0xC9]);
+}
+
+#[test]
+fn compiler_sm83_invalid() {
+    let errors = compile_err(
+        r#"
+@target(sm83)
+sub boot {
+    ld IX, $1234
+    exx
+    jp boot if Negative
+    ret
+}
+"#,
+    );
+    let messages = errors
+        .iter()
+        .map(|error| error.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        vec![
+            "The SM83 has no IX register",
+            "'exx' isn't available on the SM83",
+            "'jp' can only be conditional on Zero or Carry",
+        ]
+    );
+    assert_eq!(errors[0].span.col, 7..9);
+
+    let errors = compile_err(
+        r#"
+@target(sm83)
+sub boot {
+    ldh A, $1234*
+    ret
+}
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "Address '$1234' isn't in the high page $FF00..$FFFF"
+    );
+
+    let errors = compile_err(
+        r#"
+sub boot {
+    swap A
+    ret
+}
+"#,
+    );
+    assert_eq!(errors[0].message, "'swap' isn't available on the Z80");
+}
+
+#[test]
+fn compiler_cartridge_header() {
+    let binary = compile_ok(
+        r#"
+@target(sm83)
+@cartridge("ZIRCON", boot)
+@origin($0150)
+sub boot {
+    jp boot
+}
+"#,
+    );
+
+    assert_eq!(binary.len(), 0x8000);
+    assert_eq!(binary[0x100..0x104], [0x00, 0xC3, 0x50, 0x01]);
+    assert_eq!(binary[0x104..0x108], [0xCE, 0xED, 0x66, 0x66]);
+    assert_eq!(&binary[0x134..0x13A], b"ZIRCON");
+    assert_eq!(binary[0x148], 0x00);
+    assert_eq!(binary[0x14D], 0x11);
+    assert_eq!(binary[0x14E..0x150], [0x19, 0x55]);
+    assert_eq!(binary[0x150..0x153], [0xC3, 0x50, 0x01]);
+
+    let errors = compile_err(
+        r#"
+@cartridge("ZIRCON", boot)
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "'@cartridge' is only available with '@target(sm83)'"
+    );
+
+    let errors = compile_err(
+        r#"
+@target(sm83)
+@cartridge("A TITLE THAT IS TOO LONG", boot)
+"#,
+    );
+    assert_eq!(
+        errors[0].message,
+        "Cartridge title 'A TITLE THAT IS TOO LONG' has to be at most 15 ASCII characters"
+    );
+}
+
+#[test]
+fn compiler_mos6502() {
+    let binary = compile_ok(
+        r#"
+@target(mos6502)
+@origin($8000)
+def Pointer = $20
+def Screen = $0400
+sub boot {
+    lda 5
+    sta Pointer*
+    sta Screen*
+    lda ($10 + X)*
+    sta ($1000 + Y)*
+    ldx ($10 + Y)*
+    lda (Pointer + X)**
+    sta (Pointer* + Y)*
+    asl
+    rol A
+    inc $0300*
+    jsr helper
+    jmp $FFFC*
+}
+sub helper {
+    dex
+    bne helper
+    rts
+}
+"#,
+    );
+
+    assert_eq!(
+        binary[0x8000..],
+        [
+            0xA9, 0x05, 0x85, 0x20, 0x8D, 0x00, 0x04, 0xB5, 0x10, 0x99, 0x00, 0x10, 0xB6, 0x10,
+            0xA1, 0x20, 0x91, 0x20, 0x0A, 0x2A, 0xEE, 0x00, 0x03, 0x20, 0x1D, 0x80, 0x6C, 0xFC,
+            0xFF, 0xCA, 0xD0, 0xFD, 0x60,
+        ]
+    );
+}
+
+#[test]
+fn compiler_mos6502_zero_page() {
+    // Declarations after their use aren't known while parsing, so they're always absolute.
+    let binary = compile_ok(
+        r#"
+@target(mos6502)
+sub boot {
+    lda Later*
+    lda ($0010 + X)*
+    lda ($0100 + X)*
+    rts
+}
+def Later = $10
+"#,
+    );
+    assert_eq!(
+        binary,
+        [0xAD, 0x10, 0x00, 0xB5, 0x10, 0xBD, 0x00, 0x01, 0x60]
+    );
+}
+
+#[test]
+fn compiler_mos6502_invalid() {
+    let errors = compile_err(
+        r#"
+@target(mos6502)
+sub boot {
+    ld A, B
+    lda B
+    stx ($1234 + Y)*
+    lda ($1234 + X)**
+    lda ($10* + X)*
+    sta 5
+}
+"#,
+    );
+    let messages = errors
+        .iter()
+        .map(|error| error.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        vec![
+            "'ld' isn't available on the 6502",
+            "The 6502 has no B register",
+            "Invalid operand '($1234 + Y)*' for 'stx', expected one of a zero page address, a zero page address indexed by Y or an address",
+            "Invalid operand '($1234 + X)**' for 'lda', expected one of an 8-bit number, a zero page address, a zero page address indexed by X, an address, an address indexed by X, an address indexed by Y, ($nn + X)** or ($nn* + Y)*",
+            "Pointers can only be indexed like '($10 + X)**' or '($10* + Y)*'",
+            "Invalid operand '$05' for 'sta', expected one of a zero page address, a zero page address indexed by X, an address, an address indexed by X, an address indexed by Y, ($nn + X)** or ($nn* + Y)*",
+        ]
+    );
+    assert_eq!(errors[1].span.col, 8..9);
+
+    let errors = compile_err(
+        r#"
+sub boot {
+    ld A, ($10 + X)*
+}
+"#,
+    );
+    assert_eq!(errors[0].message, "The Z80 has no X register");
+}
+
+#[test]
+fn compiler_xy_names() {
+    let binary = compile_ok(
+        r#"
+def x = 5
+def Y = 6
+sub boot {
+    ld A, x
+    ld B, Y
+    ret
+}
+"#,
+    );
+    assert_eq!(binary, vec![0x3E, 0x05, 0x06, 0x06, 0xC9]);
+
+    let errors = compile_err(
+        r#"
+sub boot {
+    using (value = a) {
+        ret
+    }
+}
+"#,
+    );
+    assert_eq!(errors[0].message, "Unknown register 'a'");
+    assert_eq!(errors[0].span.col, 19..20);
+}
+
+#[test]
+fn compiler_other_target_mnemonics() {
+    let binary = compile_ok(
+        r#"
+def tax = 1
+sub stop {
+    ld A, tax
+    jp swap
+}
+sub swap {
+    ret
+}
+"#,
+    );
+    assert_eq!(binary, vec![0x3E, 0x01, 0xC3, 0x05, 0x00, 0xC9]);
+
+    let errors = compile_err(
+        "sub boot {
+    lda $10
+    ret
+}
+",
+    );
+    assert_eq!(errors[0].message, "'lda' isn't available on the Z80");
+    assert_eq!(errors[0].span.col, 4..7);
+}
+
+#[test]
+fn compiler_sub_terminators() {
+    compile_ok(
+        r#"
+sub jumps {
+    jp jumps
+}
+sub returns {
+    ret
+}
+sub halts {
+    ld A, B
+    hlt
+}
+sub falls {
+    nop
+    fallthrough
+}
+sub last {
+    jr last
+}
+"#,
+    );
+
+    let errors = compile_err(
+        r#"
+sub boot {
+    ld A, B
+}
+sub conditional {
+    ret if Zero
+}
+sub empty {
+}
+sub early {
+    fallthrough
+    ret
+}
+"#,
+    );
+    let messages = errors
+        .iter()
+        .map(|error| error.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        vec![
+            "Subroutine 'boot' can fall off the end, end it with a jump, 'ret', 'hlt' or 'fallthrough'",
+            "Subroutine 'conditional' can fall off the end, end it with a jump, 'ret', 'hlt' or 'fallthrough'",
+            "Subroutine 'empty' can fall off the end, end it with a jump, 'ret', 'hlt' or 'fallthrough'",
+            "'fallthrough' has to be the last line of a block",
+        ]
+    );
+    assert_eq!(errors[0].span.col, 4..8);
+    assert_eq!(errors[0].span.line, 1..2);
+}
+
+#[test]
+fn compiler_fallthrough() {
+    let text = r#"
+sub first {
+    nop
+    fallthrough
+}
+@origin($0010)
+sub second {
+    ret
+}
+"#;
+    let tokens = tokenize(&mut Cursor::new(text.as_bytes())).unwrap().tokens;
+    let output = compile_output(text, &tokens);
+    let MultiResult::Err(errors) = output.result else {
+        panic!("Expected errors");
+    };
+    assert!(output.warnings.is_empty());
+    assert_eq!(
+        errors[0].message,
+        "Subroutine 'first' falls through to $0001, but no block starts there"
+    );
+    assert_eq!(errors[0].span.line, 3..4);
+
+    let text = r#"
+sub first {
+    nop
+    fallthrough
+}
+@origin($0010)
+sub second {
+    ret
+}
+@origin($0001)
+sub third {
+    ret
+}
+"#;
+    let tokens = tokenize(&mut Cursor::new(text.as_bytes())).unwrap().tokens;
+    let output = compile_output(text, &tokens);
+    assert!(matches!(output.result, MultiResult::Ok(_)));
+    let messages = output
+        .warnings
+        .iter()
+        .map(|warning| warning.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        vec!["Subroutine 'first' falls through into 'third' at $0001, not into 'second' which follows it"]
+    );
+}
+
+#[test]
+fn compiler_var() {
+    let text = r#"
+@region(code, $0000, $8000, ROM)
+@region(wram, $C000, $0100, RAM)
+var counter: 1
+var buffer: 16
+sub boot {
+    ld A, counter*
+    inc A
+    ld counter*, A
+    ld HL, &buffer
+    ret
+}
+"#;
+    let tokens = tokenize(&mut Cursor::new(text.as_bytes())).unwrap().tokens;
+    let output = compile_output(text, &tokens);
+    let MultiResult::Ok(binary) = output.result else {
+        panic!("Expected success, got {:#?}", output.result);
+    };
+    assert_eq!(
+        binary,
+        [0x3A, 0x00, 0xC0, 0x3C, 0x32, 0x00, 0xC0, 0x21, 0x01, 0xC0, 0xC9]
+    );
+
+    let symbols = output
+        .symbols
+        .iter()
+        .map(|symbol| symbol.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        symbols,
+        [
+            "$0000 boot (sub)",
+            "$C000 counter (var, 1 byte)",
+            "$C001 buffer (var, 16 bytes)",
+        ]
+    );
+
+    // Variables go into the next RAM region once the first one is full.
+    let binary = compile_ok(
+        r#"
+@region(code, $0000, $8000, ROM)
+@region(small, $8000, 3, RAM)
+@region(large, $C000, $4000, RAM)
+var first: 2
+var second: 2
+sub boot {
+    ld A, second*
+    ret
+}
+"#,
+    );
+    assert_eq!(binary, [0x3A, 0x00, 0xC0, 0xC9]);
+}
+
+#[test]
+fn compiler_var_invalid() {
+    let errors = compile_err(
+        r#"
+var counter: 1
+@region(wram, $C000, 4, RAM)
+var big: 5
+var empty: 0
+var fits: 4
+var full: 1
+"#,
+    );
+    let messages = errors
+        .iter()
+        .map(|error| error.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        vec![
+            "Variable 'counter' needs a RAM region, declare one with '@region(name, start, size, RAM)'",
+            "Variable 'big' doesn't fit into the free RAM, it needs 5 bytes",
+            "Variable 'empty' has to be at least 1 byte",
+            "Variable 'full' doesn't fit into the free RAM, it needs 1 byte",
+        ]
+    );
+
+    let errors = compile_err(
+        r#"
+@region(ram, $0000, $0010, RAM)
+var counter: 2
+sub boot {
+    ret
+}
+"#,
+    );
+    let messages = errors
+        .iter()
+        .map(|error| error.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        vec![
+            "Subroutine 'boot' overlaps with 'counter'",
+            "Subroutine 'boot' is in the RAM region 'ram', it has to be in a ROM region",
+        ]
+    );
+}
+
+#[test]
+fn compiler_regions() {
+    let text = r#"
+@region(code, $0000, $8000, ROM)
+@region(io, $8000, $0100, MMIO)
+@region(ram, $C000, $4000, RAM)
+var buffer: $0400
+sub boot {
+    jp boot
+}
+rom vector: 2 = boot
+"#;
+    let tokens = tokenize(&mut Cursor::new(text.as_bytes())).unwrap().tokens;
+    let output = compile_output(text, &tokens);
+    assert!(matches!(output.result, MultiResult::Ok(_)));
+
+    let regions = output
+        .regions
+        .iter()
+        .map(|region| region.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        regions,
+        [
+            "ROM code $0000..$8000: 5 of 32768 bytes used (0%)",
+            "MMIO io $8000..$8100: 0 of 256 bytes used (0%)",
+            "RAM ram $C000..$10000: 1024 of 16384 bytes used (6%)",
+        ]
+    );
+
+    let errors = compile_err(
+        r#"
+@region(code, $0000, $1000, ROM)
+@region(io, $8000, $0100, MMIO)
+@region(overlapping, $0800, $1000, RAM)
+@region(wrong, $A000, $10, EEPROM)
+@region(huge, $F000, $2000, RAM)
+@origin($0FFE)
+sub crossing {
+    jp crossing
+}
+@origin($8000)
+sub in_io {
+    ret
+}
+rom data: 2 = in_io
+"#,
+    );
+    let messages = errors
+        .iter()
+        .map(|error| error.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        vec![
+            "Region 'overlapping' overlaps with 'code'",
+            "Unknown region kind 'EEPROM', expected one of ROM, RAM, MMIO",
+            "Region 'huge' ends at $11000, past the end of memory at $10000",
+            "Subroutine 'crossing' at $0FFE..$1001 isn't inside a ROM region",
+            "Subroutine 'in_io' is in the MMIO region 'io', it has to be in a ROM region",
+            "Data 'data' is in the MMIO region 'io', it has to be in a ROM region",
+        ]
+    );
+
+    let errors = compile_err(
+        r#"
+@region(code, $0000, $1000, ROM)
+sub boot {
+    ret
+}
+rom vector: 2 = missing
+"#,
+    );
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].message, "Unknown label 'missing'");
+    assert_eq!(errors[0].span.line, 5..6);
+    assert_eq!(errors[0].span.col, 16..23);
+}
+
+#[test]
+fn compiler_using() {
+    let binary = compile_ok(
+        r#"
+sub copy {
+    using (source = HL, target = DE, count = B) {
+        ld A, source*
+        ld target*, A
+        inc source
+        inc target
+        djnz copy
+        using (index = IX) {
+            ld (index + 2)*, count
+        }
+    }
+    ld B, H
+    ret
+}
+"#,
+    );
+    assert_eq!(
+        binary,
+        [0x7E, 0x12, 0x23, 0x13, 0x10, 0xFA, 0xDD, 0x70, 0x02, 0x44, 0xC9]
+    );
+}
+
+#[test]
+fn compiler_using_invalid() {
+    let errors = compile_err(
+        r#"
+sub boot {
+    using (ptr = HL, count = B) {
+        ld A, HL*
+        ld L, A
+        dec count
+    }
+    using (one = HL, two = HL) {
+        ret
+    }
+    using (pair = DE, low = E) {
+        ret
+    }
+    using (first = BC, first = DE) {
+        ret
+    }
+    ret
+}
+"#,
+    );
+    let messages = errors
+        .iter()
+        .map(|error| error.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        vec![
+            "'HL' can't be used directly while 'ptr' aliases HL",
+            "'L' can't be used directly while 'ptr' aliases HL",
+            "'one' and 'two' both alias HL",
+            "'low' aliases E, which overlaps with DE aliased by 'pair'",
+            "'first' already aliases BC",
+        ]
+    );
+    assert_eq!(errors[0].span.col, 14..16);
+    assert_eq!(errors[0].span.line, 3..4);
+    assert_eq!(errors[2].span.col, 27..29);
+}
+
+#[test]
+fn compiler_if() {
+    let binary = compile_ok(
+        r#"
+sub check {
+    cp $10
+    if Zero {
+        ld A, 1
+    } else {
+        ld A, 2
+    }
+    if not(Carry) {
+        inc B
+        if Negative {
+            dec C
+        }
+    }
+    ret
+}
+"#,
+    );
+    assert_eq!(
+        binary,
+        [
+            0xFE, 0x10, 0x20, 0x04, 0x3E, 0x01, 0x18, 0x02, 0x3E, 0x02, 0x38, 0x05, 0x04, 0xF2,
+            0x11, 0x00, 0x0D, 0xC9
+        ]
+    );
+
+    // Both blocks end in a jump, so the subroutine can't fall off the end and there's no jump over `else`.
+    let binary = compile_ok(
+        r#"
+sub check {
+    if Carry {
+        ret
+    }
+    else {
+        jp check
+    }
+}
+"#,
+    );
+    assert_eq!(binary, [0x30, 0x01, 0xC9, 0xC3, 0x00, 0x00]);
+}
+
+#[test]
+fn compiler_if_long() {
+    let short = format!(
+        "sub long {{\n if Zero {{\n{} }}\n ret\n}}",
+        "nop\n".repeat(127)
+    );
+    let binary = compile_ok(&short);
+    assert_eq!(binary[..2], [0x20, 0x7F]);
+    assert_eq!(binary.len(), 2 + 127 + 1);
+
+    let long = format!(
+        "sub long {{\n if Zero {{\n{} }}\n ret\n}}",
+        "nop\n".repeat(128)
+    );
+    let binary = compile_ok(&long);
+    assert_eq!(binary[..3], [0xC2, 0x83, 0x00]);
+    assert_eq!(binary.len(), 3 + 128 + 1);
+
+    let binary = compile_ok(
+        r#"
+@target(i8080)
+sub check {
+    if Zero {
+        ret
+    }
+    ret
+}
+"#,
+    );
+    assert_eq!(binary, [0xC2, 0x04, 0x00, 0xC9, 0xC9]);
+
+    let binary = compile_ok(
+        r#"
+@target(mos6502)
+sub check {
+    cmp $10
+    if Zero {
+        lda $01
+    } else {
+        lda $02
+    }
+    rts
+}
+"#,
+    );
+    assert_eq!(
+        binary,
+        [0xC9, 0x10, 0xD0, 0x05, 0xA9, 0x01, 0x4C, 0x0B, 0x00, 0xA9, 0x02, 0x60]
+    );
+}
+
+#[test]
+fn compiler_if_invalid() {
+    let errors = compile_err(
+        r#"
+sub check {
+    if Zro {
+        ret
+    }
+    if not(Carry {
+        ret
+    }
+    if Zero {
+        fallthrough
+    }
+    else {
+        ret
+    }
+    nop
+    else {
+        ret
+    }
+    if Zero {
+        ret
+    }
+}
+"#,
+    );
+    let messages = errors
+        .iter()
+        .map(|error| error.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        vec![
+            "Unknown condition 'Zro', expected one of Zero, Carry, Overflow, ParityEven, ParityOdd, Negative, Positive",
+            "Expected ClosingParen, found OpeningCurly",
+            "'fallthrough' can only end a subroutine",
+            "'else' has to follow the '}' of an 'if' block",
+            "Subroutine 'check' can fall off the end, end it with a jump, 'ret', 'hlt' or 'fallthrough'",
+        ]
+    );
+    assert_eq!(errors[0].span.line, 2..3);
+    assert_eq!(errors[0].span.col, 7..10);
+    assert_eq!(errors[1].span.line, 5..6);
+    assert_eq!(errors[1].span.col, 17..18);
+}
+
+#[test]
+fn compiler_loops() {
+    let binary = compile_ok(
+        r#"
+sub run {
+    ld B, 4
+    repeat B {
+        inc A
+        continue if Zero
+        dec C
+    }
+    while not(Zero) {
+        dec A
+        break if Carry
+    }
+    loop {
+        inc HL
+        cp $FF
+        break if Zero
+    }
+    ret
+}
+"#,
+    );
+    assert_eq!(
+        binary,
+        [
+            0x06, 0x04, 0x3C, 0x28, 0x01, 0x0D, 0x10, 0xFA, 0x28, 0x05, 0x3D, 0x38, 0x02, 0x18,
+            0xF9, 0x23, 0xFE, 0xFF, 0x28, 0x02, 0x18, 0xF9, 0xC9
+        ]
+    );
+
+    // Without a `break` the loop never ends, so the subroutine can't fall off the end.
+    let binary = compile_ok(
+        r#"
+sub forever {
+    loop {
+        inc A
+    }
+}
+"#,
+    );
+    assert_eq!(binary, [0x3C, 0x18, 0xFD]);
+
+    let binary = compile_ok(
+        r#"
+@target(mos6502)
+sub run {
+    ldx $08
+    repeat X {
+        iny
+    }
+    loop {
+        dey
+        break if Zero
+    }
+    rts
+}
+"#,
+    );
+    assert_eq!(
+        binary,
+        [0xA2, 0x08, 0xC8, 0xCA, 0xD0, 0xFC, 0x88, 0xF0, 0x03, 0x4C, 0x06, 0x00, 0x60]
+    );
+}
+
+#[test]
+fn compiler_loops_invalid() {
+    let text = r#"
+sub run {
+    break
+    repeat C {
+        inc A
+    }
+    while Zro {
+        inc A
+    }
+    repeat B {
+        break if Carry
+NOPS    }
+    loop {
+NOPS    }
+}
+"#
+    .replace("NOPS", &"nop\n".repeat(130));
+    let errors = compile_err(&text);
+    let messages = errors
+        .iter()
+        .map(|error| error.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        vec![
+            "'break' has to be inside a loop",
+            "'repeat' counts down with 'djnz', which only uses B, found C",
+            "Unknown condition 'Zro', expected one of Zero, Carry, Overflow, ParityEven, ParityOdd, Negative, Positive",
+            "'break' can't reach the end of the loop, the displacement 132 doesn't fit into -128..127",
+            "The 'repeat' body is too long for 'djnz' to jump back to its start, the displacement -134 doesn't fit into -128..127",
+            "The 'loop' body is too long to jump back to its start, the displacement -132 doesn't fit into -128..127",
+        ]
+    );
+    assert_eq!(errors[1].span.line, 3..4);
+    assert_eq!(errors[1].span.col, 11..12);
+    assert_eq!(errors[3].span.line, 10..11);
+    assert_eq!(errors[3].span.col, 8..13);
+
+    let errors = compile_err("@target(sm83)\nsub run {\n repeat B {\n inc A\n }\n ret\n}");
+    assert_eq!(
+        errors[0].message,
+        "'repeat' needs 'djnz', which the SM83 doesn't have"
+    );
+}
+
+#[test]
+fn compiler_modules() {
+    let output = compile_files(&[
+        (
+            "main.zir",
+            r#"
+sub boot {
+    call vdp::init
+    ld A, vdp::Mode
+    ret
+}
+
+import "drivers/vdp.zir"
+"#,
+        ),
+        (
+            "drivers/vdp.zir",
+            r#"
+import "regs.zir"
+
+pub def Mode = $02
+
+pub sub init {
+    ld A, regs::Control
+    call helper
+    ret
+}
+
+sub helper {
+    ret
+}
+"#,
+        ),
+        ("drivers/regs.zir", "pub def Control = $99\n"),
+    ]);
+
+    let MultiResult::Ok(binary) = output.result else {
+        panic!("Expected success, got {:#?}", output.result);
+    };
+    assert_eq!(
+        binary,
+        [0xCD, 0x06, 0x00, 0x3E, 0x02, 0xC9, 0x3E, 0x99, 0xCD, 0x0C, 0x00, 0xC9, 0xC9]
+    );
+
+    let symbols = output
+        .symbols
+        .iter()
+        .map(|symbol| symbol.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        symbols,
+        [
+            "$0000 boot (sub)",
+            "$0002 vdp::Mode (def)",
+            "$0006 vdp::init (sub)",
+            "$000C vdp::helper (sub)",
+            "$0099 regs::Control (def)",
+        ]
+    );
+}
+
+#[test]
+fn compiler_modules_relative_paths() {
+    let output = compile_files(&[
+        (
+            "./main.zir",
+            r#"
+import "lib/gfx.zir"
+import "lib/../lib/util.zir"
+sub boot {
+    ld A, util::Value
+    ret
+}
+"#,
+        ),
+        ("lib/gfx.zir", "import \"./util.zir\"\n"),
+        ("lib/util.zir", "pub def Value = $12\n"),
+    ]);
+
+    let MultiResult::Ok(binary) = output.result else {
+        panic!("Expected success, got {:#?}", output.result);
+    };
+    assert_eq!(binary, [0x3E, 0x12, 0xC9]);
+}
+
+#[test]
+fn compiler_modules_invalid() {
+    let errors = |files: &[(&str, &str)]| match compile_files(files).result {
+        MultiResult::Ok(binary) => panic!("Expected errors, got {:#04X?}", binary),
+        MultiResult::Err(errors) => errors,
+    };
+
+    let private = errors(&[
+        (
+            "main.zir",
+            "import \"vdp.zir\"\nsub boot {\n    call vdp::helper\n    ret\n}\n",
+        ),
+        ("vdp.zir", "sub helper {\n    ret\n}\n"),
+    ]);
+    assert_eq!(
+        private[0].message,
+        "'vdp::helper' isn't public, declare it with 'pub' to use it from another module"
+    );
+    assert_eq!(private[0].span.file, 0);
+    assert_eq!(private[0].span.line, 2..3);
+    assert_eq!(private[0].span.col, 9..20);
+
+    let cycle = errors(&[
+        ("x.zir", "import \"y.zir\"\nimport \"missing.zir\"\n"),
+        ("y.zir", "pub def Value = 1\nimport \"x.zir\"\n"),
+    ]);
+    let messages = cycle
+        .iter()
+        .map(|error| error.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        [
+            "Cyclic import 'x.zir' -> 'y.zir' -> 'x.zir'",
+            "Can't read 'missing.zir': not found",
+        ]
+    );
+    assert_eq!(cycle[0].span.file, 1);
+    assert_eq!(cycle[0].span.line, 1..2);
+    assert_eq!(cycle[0].span.col, 0..14);
+    assert_eq!(cycle[1].span.file, 0);
+
+    let names = errors(&[
+        (
+            "main.zir",
+            "import \"hl.zir\"\nimport \"out.zir\"\nimport \"loop.zir\"\nimport \"my-mod.zir\"\n",
+        ),
+        ("hl.zir", "pub def Value = 1\n"),
+        ("out.zir", "pub def Value = 1\n"),
+        ("loop.zir", "pub def Value = 1\n"),
+        ("my-mod.zir", "pub def Value = 1\n"),
+    ]);
+    let messages = names
+        .iter()
+        .map(|error| error.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        [
+            "'hl' can't be used as a module name, it's a register",
+            "'out' can't be used as a module name, it's a mnemonic",
+            "'loop' can't be used as a module name, it's a keyword",
+            "'my-mod' can't be used as a module name",
+        ]
+    );
+    assert_eq!(names[0].span.line, 0..1);
+    assert_eq!(names[0].span.col, 0..15);
+
+    let parent_cycle = errors(&[
+        ("main.zir", "import \"lib/b.zir\"\n"),
+        ("lib/b.zir", "import \"../main.zir\"\n"),
+    ]);
+    assert_eq!(
+        parent_cycle[0].message,
+        "Cyclic import 'main.zir' -> 'lib/b.zir' -> 'main.zir'"
+    );
+
+    let clash = errors(&[
+        (
+            "main.zir",
+            "import \"a/util.zir\"\nimport \"b/util.zir\"\npub ld A, B\n",
+        ),
+        ("a/util.zir", "def Value = 1\n"),
+        ("b/util.zir", "def Value = 2\n"),
+    ]);
+    let messages = clash
+        .iter()
+        .map(|error| error.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        [
+            "Module 'util' is already imported from 'a/util.zir'",
+            "'pub' has to be followed by a 'sub', 'def', 'rom' or 'var'",
+        ]
+    );
+
+    let single = compile_err("import \"vdp.zir\"\n");
+    assert_eq!(
+        single[0].message,
+        "Can't import 'vdp.zir' from a single file, load the files with 'Sources'"
+    );
+}
+
+#[test]
+fn compiler_include() {
+    let output = compile_files(&[
+        (
+            "main.zir",
+            r#"
+@include("consts.zir")
+sub boot {
+    ld A, Value
+    ret
+}
+@include("lib/util.zir")
+"#,
+        ),
+        ("consts.zir", "def Value = $12\n"),
+        (
+            "lib/util.zir",
+            "sub util {\n    ret\n}\n@include(\"more.zir\")\n",
+        ),
+        ("lib/more.zir", "sub more {\n    ld B, 1\n    ret\n}\n"),
+    ]);
+
+    let MultiResult::Ok(binary) = output.result else {
+        panic!("Expected success, got {:#?}", output.result);
+    };
+    assert_eq!(binary, [0x3E, 0x12, 0xC9, 0xC9, 0x06, 0x01, 0xC9]);
+
+    let symbols = output
+        .symbols
+        .iter()
+        .map(|symbol| symbol.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        symbols,
+        [
+            "$0000 boot (sub)",
+            "$0003 util (sub)",
+            "$0004 more (sub)",
+            "$0012 Value (def)",
+        ]
+    );
+}
+
+#[test]
+fn compiler_commented_dependencies() {
+    let output = compile_files(&[(
+        "main.zir",
+        r#"
+// import "old.zir"
+sub boot {
+    // @include("gone.zir")
+    ld A, 1 // @incbin(font, "gone.bin")
+    ret
+}
+"#,
+    )]);
+
+    let MultiResult::Ok(binary) = output.result else {
+        panic!("Expected success, got {:#?}", output.result);
+    };
+    assert_eq!(binary, [0x3E, 0x01, 0xC9]);
+}
+
+#[test]
+fn compiler_include_twice() {
+    let output = compile_files(&[
+        (
+            "main.zir",
+            r#"
+import "vdp.zir"
+@include("consts.zir")
+@include("consts.zir")
+sub boot {
+    ld A, Value
+    ld B, vdp::Mode
+    ret
+}
+@include("consts.zir")
+"#,
+        ),
+        (
+            "vdp.zir",
+            "@include(\"consts.zir\")\npub def Mode = $34\n@include(\"consts.zir\")\n",
+        ),
+        ("consts.zir", "def Value = $12\n"),
+    ]);
+
+    let MultiResult::Ok(binary) = output.result else {
+        panic!("Expected success, got {:#?}", output.result);
+    };
+    assert_eq!(binary, [0x3E, 0x12, 0x06, 0x34, 0xC9]);
+}
+
+#[test]
+fn compiler_include_blocks_in_modules() {
+    let output = compile_files(&[
+        (
+            "main.zir",
+            r#"
+import "mod1.zir"
+@include("shared.zir")
+"#,
+        ),
+        ("mod1.zir", "@include(\"shared.zir\")\n"),
+        (
+            "shared.zir",
+            "sub wait {\n    loop {\n        if Zero {\n            ret\n        }\n    }\n}\n",
+        ),
+    ]);
+
+    let MultiResult::Ok(binary) = output.result else {
+        panic!("Expected success, got {:#?}", output.result);
+    };
+    assert_eq!(
+        binary,
+        [0x20, 0x01, 0xC9, 0x18, 0xFB, 0x20, 0x01, 0xC9, 0x18, 0xFB]
+    );
+}
+
+#[test]
+fn compiler_duplicate_declarations() {
+    let errors = compile_err(
+        r#"
+def Value = 1
+sub boot {
+    ret
+}
+sub boot {
+    ret
+}
+def Value = 2
+"#,
+    );
+    let messages = errors
+        .iter()
+        .map(|error| error.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        ["'boot' is already declared", "'Value' is already declared"]
+    );
+    assert_eq!(errors[0].span.line, 5..6);
+    assert_eq!(errors[0].span.col, 4..8);
+}
+
+#[test]
+fn compiler_include_invalid() {
+    let errors = match compile_files(&[
+        ("a.zir", "@include(\"b.zir\")\n@include(\"nope.zir\")\n"),
+        (
+            "b.zir",
+            "sub broken {\n    ld A\n    ret\n}\n@include(\"a.zir\")\n",
+        ),
+    ])
+    .result
+    {
+        MultiResult::Ok(binary) => panic!("Expected errors, got {:#04X?}", binary),
+        MultiResult::Err(errors) => errors,
+    };
+
+    let messages = errors
+        .iter()
+        .map(|error| error.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        [
+            "Cyclic include 'a.zir' -> 'b.zir' -> 'a.zir'",
+            "Can't read 'nope.zir': not found",
+            "'ld' expects 2 operands, found 1",
+        ]
+    );
+    assert_eq!(errors[0].span.file, 1);
+    assert_eq!(errors[0].span.line, 4..5);
+    assert_eq!(errors[0].span.col, 0..17);
+    assert_eq!(errors[1].span.file, 0);
+    assert_eq!(errors[1].span.line, 1..2);
+    assert_eq!(errors[1].span.col, 0..20);
+    assert_eq!(errors[2].span.file, 1);
+    assert_eq!(errors[2].span.line, 1..2);
+
+    let single = compile_err("@include(\"consts.zir\")\n");
+    assert_eq!(
+        single[0].message,
+        "Can't include 'consts.zir' from a single file, load the files with 'Sources'"
+    );
+}
+
+#[test]
+fn compiler_incbin() {
+    let output = compile_files_with_binaries(
+        &[(
+            "main.zir",
+            r#"
+sub boot {
+    ld HL, font
+    ld BC, sizeof(font)
+    ld DE, sizeof(boot)
+    ret
+}
+@incbin(font, "gfx/font.bin")
+@incbin("gfx/font.bin", 1, 2)
+@incbin(tail, "gfx/font.bin", 3)
+"#,
+        )],
+        &[("gfx/font.bin", &[0x10, 0x20, 0x30, 0x40, 0x50])],
+    );
+
+    let MultiResult::Ok(binary) = output.result else {
+        panic!("Expected success, got {:#?}", output.result);
+    };
+    assert_eq!(
+        binary,
+        [
+            0x21, 0x0A, 0x00, 0x01, 0x05, 0x00, 0x11, 0x0A, 0x00, 0xC9, 0x10, 0x20, 0x30, 0x40,
+            0x50, 0x20, 0x30, 0x40, 0x50
+        ]
+    );
+
+    let symbols = output
+        .symbols
+        .iter()
+        .map(|symbol| symbol.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        symbols,
+        ["$0000 boot (sub)", "$000A font (rom)", "$0011 tail (rom)"]
+    );
+}
+
+#[test]
+fn compiler_incbin_invalid() {
+    let errors = match compile_files_with_binaries(
+        &[(
+            "main.zir",
+            r#"
+sub boot {
+    ld A, B
+    ret
+}
+@incbin("nope.bin")
+@incbin("data.bin", 9)
+@incbin("data.bin", 2, 4)
+@origin(1)
+@incbin(data, "data.bin")
+"#,
+        )],
+        &[("data.bin", &[1, 2, 3, 4, 5])],
+    )
+    .result
+    {
+        MultiResult::Ok(binary) => panic!("Expected errors, got {:#04X?}", binary),
+        MultiResult::Err(errors) => errors,
+    };
+
+    let messages = errors
+        .iter()
+        .map(|error| error.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        [
+            "Can't read 'nope.bin': not found",
+            "Offset 9 is past the end of 'data.bin', which is 5 bytes",
+            "'data.bin' only has 3 bytes after offset 2, not 4",
+            "Data 'data' overlaps with 'boot'",
+        ]
+    );
+    assert_eq!(errors[0].span.line, 5..6);
+    assert_eq!(errors[0].span.col, 0..18);
+    assert_eq!(errors[1].span.col, 20..21);
+
+    let single = compile_err("@incbin(\"font.bin\")\n");
+    assert_eq!(
+        single[0].message,
+        "Can't embed 'font.bin' from a single file, load the files with 'Sources'"
+    );
+}
+
+#[test]
+fn compiler_error_on_first_line() {
+    let text = "sub boot { ld Q, A }";
+    let TokenizerResult { tokens, lines } = tokenize(&mut Cursor::new(text.as_bytes())).unwrap();
+    let MultiResult::Err(errors) = compile(text, &tokens) else {
+        panic!("Expected errors");
+    };
+    assert_eq!(errors[0].span.line, 0..1);
+    print_errors(text, &lines, errors, 1);
+
+    let sources = Sources::load_with("main.zir", |path| match path.to_str() {
+        Some("main.zir") => Ok(b"import \"missing.zir\"\n".to_vec()),
+        _ => Err(io::Error::new(io::ErrorKind::NotFound, "not found")),
+    })
+    .unwrap();
+    assert_eq!(sources.errors[0].span.line, 0..1);
+    print_source_errors(&sources, sources.errors.clone(), 1);
+}
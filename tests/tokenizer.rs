@@ -32,6 +32,7 @@ sub boot {
         vec![Token {
             ty: Error,
             span: Span {
+                file: 0,
                 pos: 23..25,
                 line: 2..3,
                 col: 11..13
@@ -55,6 +56,7 @@ ld A $FF
             Token {
                 ty: NewLine,
                 span: Span {
+                    file: 0,
                     pos: 0..1,
                     line: 0..1,
                     col: 0..1
@@ -63,6 +65,7 @@ ld A $FF
             Token {
                 ty: Instruction,
                 span: Span {
+                    file: 0,
                     pos: 1..3,
                     line: 1..2,
                     col: 0..2
@@ -71,6 +74,7 @@ ld A $FF
             Token {
                 ty: Register,
                 span: Span {
+                    file: 0,
                     pos: 4..5,
                     line: 1..2,
                     col: 3..4
@@ -79,6 +83,7 @@ ld A $FF
             Token {
                 ty: HexNumber,
                 span: Span {
+                    file: 0,
                     pos: 6..9,
                     line: 1..2,
                     col: 5..8
@@ -87,6 +92,7 @@ ld A $FF
             Token {
                 ty: NewLine,
                 span: Span {
+                    file: 0,
                     pos: 9..10,
                     line: 1..2,
                     col: 8..9
@@ -114,6 +120,7 @@ sub boot {
             Token {
                 ty: NewLine,
                 span: Span {
+                    file: 0,
                     pos: 0..1,
                     line: 0..1,
                     col: 0..1
@@ -122,6 +129,7 @@ sub boot {
             Token {
                 ty: LabelSpecifier,
                 span: Span {
+                    file: 0,
                     pos: 1..4,
                     line: 1..2,
                     col: 0..3
@@ -130,6 +138,7 @@ sub boot {
             Token {
                 ty: Identifier,
                 span: Span {
+                    file: 0,
                     pos: 5..9,
                     line: 1..2,
                     col: 4..8
@@ -138,6 +147,7 @@ sub boot {
             Token {
                 ty: OpeningCurly,
                 span: Span {
+                    file: 0,
                     pos: 10..11,
                     line: 1..2,
                     col: 9..10
@@ -146,6 +156,7 @@ sub boot {
             Token {
                 ty: NewLine,
                 span: Span {
+                    file: 0,
                     pos: 11..12,
                     line: 1..2,
                     col: 10..11
@@ -154,6 +165,7 @@ sub boot {
             Token {
                 ty: Identifier,
                 span: Span {
+                    file: 0,
                     pos: 16..19,
                     line: 2..3,
                     col: 4..7
@@ -162,6 +174,7 @@ sub boot {
             Token {
                 ty: Identifier,
                 span: Span {
+                    file: 0,
                     pos: 20..24,
                     line: 2..3,
                     col: 8..12
@@ -170,6 +183,7 @@ sub boot {
             Token {
                 ty: NewLine,
                 span: Span {
+                    file: 0,
                     pos: 24..25,
                     line: 2..3,
                     col: 12..13
@@ -178,14 +192,16 @@ sub boot {
             Token {
                 ty: At,
                 span: Span {
+                    file: 0,
                     pos: 29..30,
                     line: 3..4,
                     col: 4..5
                 }
             },
             Token {
-                ty: Identifier,
+                ty: Keyword,
                 span: Span {
+                    file: 0,
                     pos: 30..41,
                     line: 3..4,
                     col: 5..16
@@ -194,6 +210,7 @@ sub boot {
             Token {
                 ty: NewLine,
                 span: Span {
+                    file: 0,
                     pos: 41..42,
                     line: 3..4,
                     col: 16..17
@@ -202,6 +219,7 @@ sub boot {
             Token {
                 ty: ClosingCurly,
                 span: Span {
+                    file: 0,
                     pos: 42..43,
                     line: 4..5,
                     col: 0..1
@@ -210,6 +228,7 @@ sub boot {
             Token {
                 ty: NewLine,
                 span: Span {
+                    file: 0,
                     pos: 43..44,
                     line: 4..5,
                     col: 1..2
@@ -218,3 +237,90 @@ sub boot {
         ]
     );
 }
+
+#[test]
+fn tokenizer_shadow_register() {
+    let TokenizerResult { tokens, lines: _ } =
+        tokenize(&mut Cursor::new(br#"ex AF, AF'"#)).unwrap();
+
+    assert_eq!(
+        tokens,
+        vec![
+            Token {
+                ty: Instruction,
+                span: Span {
+                    file: 0,
+                    pos: 0..2,
+                    line: 0..1,
+                    col: 0..2
+                }
+            },
+            Token {
+                ty: Register,
+                span: Span {
+                    file: 0,
+                    pos: 3..5,
+                    line: 0..1,
+                    col: 3..5
+                }
+            },
+            Token {
+                ty: Comma,
+                span: Span {
+                    file: 0,
+                    pos: 5..6,
+                    line: 0..1,
+                    col: 5..6
+                }
+            },
+            Token {
+                ty: Register,
+                span: Span {
+                    file: 0,
+                    pos: 7..10,
+                    line: 0..1,
+                    col: 7..10
+                }
+            }
+        ]
+    );
+}
+
+#[test]
+fn tokenizer_string() {
+    let TokenizerResult { tokens, lines: _ } =
+        tokenize(&mut Cursor::new(br#"("GAME" "open"#)).unwrap();
+
+    assert_eq!(
+        tokens,
+        vec![
+            Token {
+                ty: OpeningParen,
+                span: Span {
+                    file: 0,
+                    pos: 0..1,
+                    line: 0..1,
+                    col: 0..1
+                }
+            },
+            Token {
+                ty: String,
+                span: Span {
+                    file: 0,
+                    pos: 1..7,
+                    line: 0..1,
+                    col: 1..7
+                }
+            },
+            Token {
+                ty: Error,
+                span: Span {
+                    file: 0,
+                    pos: 8..13,
+                    line: 0..1,
+                    col: 8..13
+                }
+            }
+        ]
+    );
+}